@@ -0,0 +1,62 @@
+//! Raw deflate (RFC 1951) compression for hydration data.
+//!
+//! Browsers decode the output with `new DecompressionStream("deflate-raw")`.
+
+/// The compression level for hydration data. Pages are compressed on every request, so this trades a little size for
+/// speed compared to the maximum level.
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Compress data into a raw deflate stream
+pub(crate) fn deflate_raw(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(data, COMPRESSION_LEVEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::deflate_raw;
+    use miniz_oxide::inflate::decompress_to_vec;
+
+    fn round_trip(data: &[u8]) {
+        let compressed = deflate_raw(data);
+        assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn round_trips_empty_data() {
+        round_trip(b"");
+    }
+
+    #[test]
+    fn round_trips_short_data() {
+        round_trip(b"a");
+        round_trip(b"hydration");
+    }
+
+    #[test]
+    fn round_trips_repeated_data() {
+        let data = br#"{"id":1,"name":"post"}"#.repeat(2000);
+        let compressed = deflate_raw(&data);
+        assert!(compressed.len() < data.len() / 10);
+        assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn round_trips_binary_data_larger_than_the_window() {
+        // A simple xorshift generator so the data is the same on every run
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let data: Vec<u8> = (0..100_000)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                // Mix random bytes with runs that repeat from far back in the data
+                if i % 3 == 0 {
+                    (i % 251) as u8
+                } else {
+                    state as u8
+                }
+            })
+            .collect();
+        round_trip(&data);
+    }
+}
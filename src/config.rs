@@ -1,22 +1,53 @@
 //! Configuration for how to serve a Dioxus application
 #![allow(non_snake_case)]
 
-use dioxus_core::LaunchConfig;
+use dioxus_core::{CapturedError, Element, LaunchConfig};
+use dioxus_fullstack_core::HttpError;
+use http::{request::Parts, StatusCode};
 use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::{IncrementalRendererConfig, IndexHtml};
+use crate::{streaming::FlushPolicy, IncrementalRendererConfig, IndexHtml};
+
+pub(crate) type ErrorStatusMapper = Arc<dyn Fn(&CapturedError) -> Option<HttpError> + Send + Sync>;
 
 #[allow(unused)]
 pub(crate) type ContextProviders = Arc<Vec<Box<dyn Fn() -> Box<dyn Any> + Send + Sync + 'static>>>;
 
+pub(crate) type ContextProvider = Arc<dyn Fn() -> Box<dyn Any> + Send + Sync + 'static>;
+
+/// Creates a context for a request. The future resolves to a provider for the context of that request
+pub(crate) type RequestContextProvider =
+    Arc<dyn Fn(&Parts) -> Pin<Box<dyn Future<Output = ContextProvider>>> + Send + Sync + 'static>;
+
 /// A ServeConfig is used to configure how to serve a Dioxus application. It contains information about how to serve static assets, and what content to render with [`dioxus_ssr`].
 #[derive(Clone)]
 pub struct ServeConfig {
     pub(crate) index: IndexHtml,
     pub(crate) incremental: Option<IncrementalRendererConfig>,
-    pub(crate) context_providers: Vec<Arc<dyn Fn() -> Box<dyn Any> + Send + Sync + 'static>>,
+    pub(crate) context_providers: Vec<ContextProvider>,
+    pub(crate) request_context_providers: Vec<RequestContextProvider>,
     pub(crate) streaming_mode: StreamingMode,
+    pub(crate) error_pages: HashMap<StatusClass, ErrorPage>,
+    pub(crate) error_status_mappers: Vec<ErrorStatusMapper>,
+    pub(crate) server_timing: bool,
+    pub(crate) suspense_diagnostics: Option<Duration>,
+    pub(crate) csp_nonce: bool,
+    pub(crate) content_security_policy: Option<String>,
+    pub(crate) hydration_payload: HydrationPayload,
+    pub(crate) compress_hydration_data: bool,
+    pub(crate) hydration_page_budget: Option<usize>,
+    pub(crate) hydration_boundary_budget: Option<usize>,
+    pub(crate) preload_headers: bool,
+    pub(crate) early_hints: bool,
+    pub(crate) early_head_flush: bool,
+    pub(crate) streaming_buffer_size: usize,
+    pub(crate) streaming_flush_policy: FlushPolicy,
+    pub(crate) referrer_redirect: Option<StatusCode>,
 }
 
 /// The streaming mode to use while rendering the page
@@ -31,6 +62,104 @@ pub enum StreamingMode {
     OutOfOrder,
 }
 
+/// A class of HTTP status codes that an [`ErrorPage`] can be registered for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatusClass {
+    /// `4xx` status codes
+    ClientError,
+
+    /// `5xx` status codes
+    ServerError,
+}
+
+impl StatusClass {
+    /// Get the class of a status code, if it is an error status
+    pub fn of(status: StatusCode) -> Option<Self> {
+        if status.is_client_error() {
+            Some(Self::ClientError)
+        } else if status.is_server_error() {
+            Some(Self::ServerError)
+        } else {
+            None
+        }
+    }
+}
+
+/// Information about a failed render that is passed to an [`ErrorPage`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorPageProps {
+    /// The status code of the response
+    pub status: StatusCode,
+
+    /// The message attached to the error, if the error was an [`HttpError`](dioxus_fullstack_core::HttpError)
+    /// or another error that is meant to be shown to the user
+    pub message: Option<String>,
+
+    /// The internal details of the error. This is only set in debug builds.
+    pub details: Option<String>,
+}
+
+impl ErrorPageProps {
+    /// The text shown for the error when there is no error page for its status
+    pub(crate) fn fallback_message(&self) -> String {
+        self.message
+            .clone()
+            .or_else(|| self.details.clone())
+            .unwrap_or_else(|| {
+                self.status
+                    .canonical_reason()
+                    .unwrap_or("An unknown error occurred")
+                    .to_string()
+            })
+    }
+}
+
+/// A page that is rendered inside the `index.html` shell when server side rendering fails before
+/// the first chunk is sent to the client.
+///
+/// Error pages are rendered without the client bootstrap scripts and hydration data. The rest of the `index.html`
+/// shell, including any content after the main element, is kept.
+#[derive(Clone)]
+pub enum ErrorPage {
+    /// Render a component with the [`ErrorPageProps`] of the failed request
+    Component(fn(ErrorPageProps) -> Element),
+
+    /// Render a static html template. The `{status}`, `{reason}`, `{message}` and `{details}`
+    /// placeholders are replaced with the escaped values from the [`ErrorPageProps`]
+    Template(String),
+}
+
+/// An error type that maps to an HTTP status when it is caught by the root error boundary during server side rendering.
+///
+/// Register the error type with [`ServeConfig::error_status`] to use the mapping.
+pub trait HttpStatusError: std::fmt::Display + std::fmt::Debug + Send + Sync + 'static {
+    /// The status code of the response
+    fn status(&self) -> StatusCode;
+
+    /// The message sent with the response. Defaults to the display representation of the error.
+    fn message(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+/// How the data required to hydrate the page is sent to the client
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum HydrationPayload {
+    /// The data is assigned to a global in an inline script after the main element
+    #[default]
+    Script,
+
+    /// The data for the root of the page is sent in a non-executable
+    /// `<script type="application/octet-stream" id="dx-hydration">` data block after the main element, and a constant
+    /// reader script is sent with the streaming initializer before the app. The data changes on every render, so this
+    /// keeps it out of inline scripts and the reader can be allowed by a hash.
+    ///
+    /// The streaming initializer, resolved suspense boundaries and `eval` calls during the render are still sent in
+    /// inline scripts. Pages that use a strict `Content-Security-Policy` still need the nonce from
+    /// [`ServeConfig::content_security_policy`] or hashes for those scripts.
+    DataBlock,
+}
+
 impl LaunchConfig for ServeConfig {}
 
 impl Default for ServeConfig {
@@ -51,8 +180,13 @@ impl ServeConfig {
     /// The `/public` folder is meant located next to the current executable. If no `index.html` file is found,
     /// a default index.html will be used, which will not include any JavaScript or WASM initialization code.
     ///
-    /// To provide an alternate `index.html`, you can use `with_index_html` method instead.
+    /// To provide an alternate `index.html`, you can use `with_index_html` method instead. On wasm32 there is no
+    /// filesystem to read the `index.html` from, so the default is always used.
     pub fn new() -> Self {
+        #[cfg(target_arch = "wasm32")]
+        let index = IndexHtml::ssr_only();
+
+        #[cfg(not(target_arch = "wasm32"))]
         let index = if let Some(public_path) = crate::server::public_path() {
             let index_html_path = public_path.join("index.html");
 
@@ -77,7 +211,24 @@ impl ServeConfig {
             index,
             incremental: None,
             context_providers: Default::default(),
+            request_context_providers: Default::default(),
             streaming_mode: StreamingMode::default(),
+            error_pages: Default::default(),
+            error_status_mappers: Default::default(),
+            server_timing: false,
+            suspense_diagnostics: None,
+            csp_nonce: false,
+            content_security_policy: None,
+            hydration_payload: HydrationPayload::default(),
+            compress_hydration_data: false,
+            hydration_page_budget: None,
+            hydration_boundary_budget: None,
+            preload_headers: false,
+            early_hints: false,
+            early_head_flush: false,
+            streaming_buffer_size: 1000,
+            streaming_flush_policy: FlushPolicy::default(),
+            referrer_redirect: Some(StatusCode::FOUND),
         }
     }
 
@@ -90,7 +241,24 @@ impl ServeConfig {
             index,
             incremental: Default::default(),
             context_providers: Default::default(),
+            request_context_providers: Default::default(),
             streaming_mode: Default::default(),
+            error_pages: Default::default(),
+            error_status_mappers: Default::default(),
+            server_timing: false,
+            suspense_diagnostics: None,
+            csp_nonce: false,
+            content_security_policy: None,
+            hydration_payload: HydrationPayload::default(),
+            compress_hydration_data: false,
+            hydration_page_budget: None,
+            hydration_boundary_budget: None,
+            preload_headers: false,
+            early_hints: false,
+            early_head_flush: false,
+            streaming_buffer_size: 1000,
+            streaming_flush_policy: FlushPolicy::default(),
+            referrer_redirect: Some(StatusCode::FOUND),
         }
     }
 
@@ -112,7 +280,8 @@ impl ServeConfig {
         self
     }
 
-    /// Provide context to the root and server functions. You can use this context while rendering with [`consume_context`](dioxus_core::consume_context).
+    /// Provide context to the root and server functions. You can use this context while rendering with [`consume_context`](dioxus_core::consume_context)
+    /// and in server functions with [`server_context`](crate::server_context).
     ///
     ///
     /// The context providers passed into this method will be called when the context type is requested which may happen many times in the lifecycle of the application.
@@ -171,7 +340,8 @@ impl ServeConfig {
     }
 
     /// Provide context to the root and server functions. You can use this context
-    /// while rendering with [`consume_context`](dioxus_core::consume_context).
+    /// while rendering with [`consume_context`](dioxus_core::consume_context) and in
+    /// server functions with [`server_context`](crate::server_context).
     ///
     ///
     /// The context providers passed into this method will be called when the context type is requested which may happen many times in the lifecycle of the application.
@@ -224,7 +394,8 @@ impl ServeConfig {
         self
     }
 
-    /// Provide context to the root and server functions. You can use this context while rendering with [`consume_context`](dioxus_core::consume_context).
+    /// Provide context to the root and server functions. You can use this context while rendering with [`consume_context`](dioxus_core::consume_context)
+    /// and in server functions with [`server_context`](crate::server_context).
     ///
     /// Context will be forwarded from the LaunchBuilder if it is provided.
     ///
@@ -271,6 +442,50 @@ impl ServeConfig {
         self
     }
 
+    /// Provide context to the root and server functions that is created once for each request from the request
+    /// parts. This is useful for contexts like the user session, tenant or locale that depend on the request headers.
+    ///
+    /// The provider runs before the app is rendered or the server function is called. The context is available
+    /// while rendering with [`consume_context`](dioxus_core::consume_context) and in server functions with
+    /// [`server_context`](crate::server_context). If a context of the same type is also provided by another
+    /// provider, the one added last is used.
+    ///
+    /// The future can't borrow the parts, so copy anything it needs out of them first:
+    ///
+    /// ```rust, no_run
+    /// #[derive(Clone)]
+    /// struct Locale(String);
+    ///
+    /// let config = dioxus_server::ServeConfig::new().request_context_provider(|parts| {
+    ///     let language = parts
+    ///         .headers
+    ///         .get(http::header::ACCEPT_LANGUAGE)
+    ///         .and_then(|value| value.to_str().ok())
+    ///         .and_then(|value| value.split(',').next())
+    ///         .unwrap_or("en")
+    ///         .to_string();
+    ///     async move { Locale(language) }
+    /// });
+    /// ```
+    pub fn request_context_provider<C, F>(
+        mut self,
+        provider: impl Fn(&Parts) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        C: Clone + Send + Sync + 'static,
+        F: Future<Output = C> + 'static,
+    {
+        self.request_context_providers
+            .push(Arc::new(move |parts: &Parts| {
+                let context = provider(parts);
+                Box::pin(async move {
+                    let context = context.await;
+                    Arc::new(move || Box::new(context.clone()) as Box<dyn Any>) as ContextProvider
+                })
+            }));
+        self
+    }
+
     /// Set the streaming mode for the server. By default, streaming is disabled.
     ///
     /// ```rust, no_run
@@ -304,4 +519,282 @@ impl ServeConfig {
         self.streaming_mode = StreamingMode::OutOfOrder;
         self
     }
+
+    /// Set the page that is rendered when server side rendering fails with a status in the given class.
+    ///
+    /// Without an error page, a plain text body with the status reason is returned. Internal error
+    /// details are only included in debug builds.
+    pub fn error_page(mut self, class: StatusClass, page: ErrorPage) -> Self {
+        self.error_pages.insert(class, page);
+        self
+    }
+
+    /// Map a user defined error type to an HTTP status when it is caught by the root error boundary during
+    /// server side rendering.
+    ///
+    /// [`HttpError`], [`StatusCode`], [`ServerFnError`](dioxus_fullstack_core::ServerFnError) and routing errors
+    /// are always mapped. Any other error without a mapping results in a `500` status.
+    pub fn error_status<E: HttpStatusError>(self) -> Self {
+        self.map_error_status(|error: &E| HttpError {
+            status: error.status(),
+            message: error.message(),
+        })
+    }
+
+    /// Map a user defined error type to an HTTP status with a function. This is useful for error types
+    /// from other crates that cannot implement [`HttpStatusError`].
+    pub fn map_error_status<E>(
+        mut self,
+        map: impl Fn(&E) -> HttpError + Send + Sync + 'static,
+    ) -> Self
+    where
+        E: std::fmt::Display + std::fmt::Debug + Send + Sync + 'static,
+    {
+        self.error_status_mappers
+            .push(Arc::new(move |error| error.downcast_ref::<E>().map(&map)));
+        self
+    }
+
+    /// Send the durations of the server side rendering phases to the client in a `Server-Timing` header. By default,
+    /// the header is disabled.
+    ///
+    /// The header includes the phases that finish before the response headers are sent: the incremental cache lookup,
+    /// building and rebuilding the virtual dom, waiting for suspense and rendering the head. Every phase, including
+    /// the first flush, each resolved suspense boundary and the total render time, is always recorded on the `ssr`
+    /// tracing span.
+    pub fn server_timing(mut self, enabled: bool) -> Self {
+        self.server_timing = enabled;
+        self
+    }
+
+    /// Enable diagnostics for suspense boundaries that are streamed out of order. By default, diagnostics are disabled.
+    ///
+    /// The time each pending suspense boundary takes to resolve is recorded along with its mount path and the
+    /// components it renders. Any boundary that takes longer than `slow_threshold` is logged with a warning. In debug
+    /// builds, a summary of the boundary timings is appended to the body in an html comment.
+    pub fn suspense_diagnostics(mut self, slow_threshold: Duration) -> Self {
+        self.suspense_diagnostics = Some(slow_threshold);
+        self
+    }
+
+    /// Generate a `Content-Security-Policy` nonce for every server rendered page. By default, nonces are disabled.
+    ///
+    /// The nonce is added to every script the server renders, including the scripts in the `index.html` and scripts
+    /// created with `document::Script`. Scripts that already set a nonce keep it. The nonce for the current request is available from
+    /// [`ServerDocument::nonce`](crate::ServerDocument::nonce) and as a [`CspNonce`](crate::CspNonce) extension in
+    /// the `FullstackContext`.
+    pub fn csp_nonce(mut self, enabled: bool) -> Self {
+        self.csp_nonce = enabled;
+        self
+    }
+
+    /// Set the `Content-Security-Policy` header for server rendered pages. Any `{nonce}` in the policy is replaced
+    /// with the nonce for the current request.
+    ///
+    /// Setting a policy with a `{nonce}` placeholder enables [`csp_nonce`](Self::csp_nonce).
+    pub fn content_security_policy(mut self, policy: impl Into<String>) -> Self {
+        let policy = policy.into();
+        self.csp_nonce |= policy.contains("{nonce}");
+        self.content_security_policy = Some(policy);
+        self
+    }
+
+    /// Write the `Content-Security-Policy` header for a request with the given nonce if a policy is set
+    pub(crate) fn write_content_security_policy(
+        &self,
+        headers: &mut http::HeaderMap,
+        nonce: Option<&str>,
+    ) {
+        let Some(policy) = &self.content_security_policy else {
+            return;
+        };
+        let policy = policy.replace("{nonce}", nonce.unwrap_or_default());
+        match http::HeaderValue::from_str(&policy) {
+            Ok(value) => {
+                headers.insert(http::header::CONTENT_SECURITY_POLICY, value);
+            }
+            Err(err) => tracing::error!("Invalid Content-Security-Policy header: {err}"),
+        }
+    }
+
+    /// Set how the data required to hydrate the page is sent to the client. By default, the data is sent in an
+    /// inline script.
+    pub fn hydration_payload(mut self, payload: HydrationPayload) -> Self {
+        self.hydration_payload = payload;
+        self
+    }
+
+    /// Compress the hydration data with deflate before it is base64 encoded. By default, compression is disabled.
+    ///
+    /// This needs a custom client. The stock Dioxus web client decodes the hydration data as plain base64 and doesn't
+    /// decompress it, so hydration fails if compression is enabled with it. Only enable compression if your client
+    /// decompresses the data with `DecompressionStream("deflate-raw")` before it hydrates.
+    ///
+    /// When compression is enabled, the head of the page sets `window.dioxus_hydration_encoding` to `"deflate-raw"`
+    /// so the client knows to decompress the initial hydration data and the data for every streamed suspense boundary.
+    /// Fragment renders mark their data block with `data-encoding="deflate-raw"`, and route data responses set
+    /// `"encoding"` to `"deflate-raw"`.
+    pub fn compress_hydration_data(mut self, enabled: bool) -> Self {
+        self.compress_hydration_data = enabled;
+        self
+    }
+
+    /// Log a warning when the total hydration data sent for a page is larger than `bytes`.
+    pub fn hydration_page_budget(mut self, bytes: usize) -> Self {
+        self.hydration_page_budget = Some(bytes);
+        self
+    }
+
+    /// Log a warning when the hydration data sent for the root of the page or a single suspense boundary is larger
+    /// than `bytes`.
+    pub fn hydration_boundary_budget(mut self, bytes: usize) -> Self {
+        self.hydration_boundary_budget = Some(bytes);
+        self
+    }
+
+    /// Send `Link` headers that preload the JS and wasm bundle from the `index.html` and any `preload`,
+    /// `modulepreload` or `preconnect` links the app adds to the head. By default, preload headers are disabled.
+    ///
+    /// The headers let the browser start loading the bundle before it parses the head. CDNs and proxies that support
+    /// Early Hints, like Cloudflare, create a `103 Early Hints` response from the `Link` headers. To send one from the
+    /// native server, enable [`early_hints`](Self::early_hints).
+    pub fn preload_headers(mut self, enabled: bool) -> Self {
+        self.preload_headers = enabled;
+        self
+    }
+
+    /// Send a `103 Early Hints` response that preloads the JS and wasm bundle from the `index.html` before the page
+    /// is rendered. By default, early hints are disabled.
+    ///
+    /// Early hints are sent by the native server that [`launch`](crate::launch) and [`serve`](crate::serve) start, to
+    /// HTTP/1.1 clients that request a full page. Links the app adds to the head are only known after the render, so
+    /// they are sent with [`preload_headers`](Self::preload_headers) instead.
+    pub fn early_hints(mut self, enabled: bool) -> Self {
+        self.early_hints = enabled;
+        self
+    }
+
+    /// Write the `Link` header with the bundle from the `index.html` and the links collected from the head if
+    /// preload headers are enabled
+    pub(crate) fn write_preload_headers(
+        &self,
+        headers: &mut http::HeaderMap,
+        head_links: &[String],
+    ) {
+        if !self.preload_headers {
+            return;
+        }
+        let links = self.index.preload_links.iter().chain(head_links);
+        let links = links.map(String::as_str).collect::<Vec<_>>().join(", ");
+        if links.is_empty() {
+            return;
+        }
+        match http::HeaderValue::from_str(&links) {
+            // The app may have set its own `Link` headers through the FullstackContext
+            Ok(value) => {
+                headers.append(http::header::LINK, value);
+            }
+            Err(err) => tracing::error!("Invalid Link header: {err}"),
+        }
+    }
+
+    /// Flush the static part of the head as soon as the status of the page is known. By default, the head is sent
+    /// once the initial chunk of the page is ready.
+    ///
+    /// This only applies to out of order streaming. The head from the `index.html` and the bootstrap scripts are
+    /// sent right after the first render of the app, before waiting for suspense, so the browser can start loading
+    /// the wasm bundle and styles while the page renders. The status and headers are sent with the head, so any
+    /// status set while suspense resolves is ignored, and errors after that point close the document instead of
+    /// returning an error page. Head elements from the app are added to the end of the head once the initial chunk
+    /// is ready.
+    pub fn early_head_flush(mut self, enabled: bool) -> Self {
+        self.early_head_flush = enabled;
+        self
+    }
+
+    /// Set how many chunks of a streaming response can be buffered before the render waits for the client to read
+    /// them. Defaults to 1000.
+    ///
+    /// A smaller buffer keeps less of a slow client's response in memory, while a larger buffer lets the render finish
+    /// sooner and return its renderer to the pool.
+    pub fn streaming_buffer_size(mut self, chunks: usize) -> Self {
+        self.streaming_buffer_size = chunks;
+        self
+    }
+
+    /// Set how many bytes of streamed html are coalesced into one frame before it is sent to the client. Defaults to
+    /// 16 KiB.
+    ///
+    /// The initial frame is always sent right away. After that, suspense boundaries that resolve together are sent in
+    /// one frame instead of one frame per boundary until the buffer reaches this size.
+    pub fn streaming_flush_threshold(mut self, bytes: usize) -> Self {
+        self.streaming_flush_policy.max_bytes = bytes;
+        self
+    }
+
+    /// Set how long resolved suspense boundaries can wait for more boundaries before they are sent to the client.
+    /// Defaults to zero, which only coalesces boundaries that resolve at the same time.
+    ///
+    /// A longer window sends fewer, larger frames for pages with many small boundaries at the cost of showing each
+    /// boundary up to this much later. There are no timers on wasm32, so the window is ignored there.
+    pub fn streaming_flush_window(mut self, window: Duration) -> Self {
+        self.streaming_flush_policy.max_delay = window;
+        self
+    }
+
+    /// Set the status used to redirect html form submissions to server functions back to the page they were
+    /// submitted from. Pass `None` to return the server function response as is. Defaults to `302 Found`.
+    ///
+    /// Without javascript, a form that posts to a server function navigates to the server function response. To keep
+    /// the user on the page, requests that accept `text/html` and have a `Referer` header are redirected back to the
+    /// referrer unless the server function set a `Location` header itself. The status should be a `3xx` redirect, like
+    /// `303 See Other`.
+    pub fn referrer_redirect(mut self, status: Option<StatusCode>) -> Self {
+        self.referrer_redirect = status;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preload_headers_are_appended_to_the_app_link_headers() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only()).preload_headers(true);
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::LINK,
+            http::HeaderValue::from_static("</style.css>; rel=preload; as=style"),
+        );
+
+        cfg.write_preload_headers(
+            &mut headers,
+            &["</assets/app.js>; rel=modulepreload".to_string()],
+        );
+
+        let links = headers
+            .get_all(http::header::LINK)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            [
+                "</style.css>; rel=preload; as=style",
+                "</assets/app.js>; rel=modulepreload"
+            ]
+        );
+    }
+
+    #[test]
+    fn preload_headers_are_disabled_by_default() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only());
+        let mut headers = http::HeaderMap::new();
+        cfg.write_preload_headers(
+            &mut headers,
+            &["</assets/app.js>; rel=modulepreload".to_string()],
+        );
+        assert!(headers.is_empty());
+    }
 }
@@ -0,0 +1,317 @@
+//! Background work that runs after the response is sent.
+use dioxus_fullstack_core::FullstackContext;
+use http::request::Parts;
+use std::{future::Future, pin::Pin, sync::Arc};
+
+/// A future passed to [`defer`]
+pub type DeferredFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+#[cfg(any(not(target_arch = "wasm32"), target_feature = "atomics"))]
+type SinkFn = dyn Fn(DeferredFuture) + Send + Sync;
+
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+type SinkFn = dyn Fn(DeferredFuture);
+
+/// Where the futures passed to [`defer`] are sent.
+///
+/// On native, server functions and renders run deferred futures on the thread that handled the request once the
+/// response is created, so no sink needs to be attached. On Workers, the entry point attaches a sink that hands the
+/// futures to `waitUntil` so the runtime keeps the isolate alive until they finish:
+///
+/// ```rust, ignore
+/// let ctx = std::rc::Rc::new(ctx);
+/// DeferSink::new(move |future| ctx.wait_until(future)).attach(&mut request);
+/// ```
+#[derive(Clone)]
+pub struct DeferSink(Arc<SinkFn>);
+
+// SAFETY: Without the atomics target feature, wasm32-unknown-unknown is single-threaded. The sink may hold JavaScript
+// values like the execution context that are not `Send` or `Sync`, but there are no other threads it could be moved to
+// or shared with. Builds with atomics require a `Send` and `Sync` sink instead.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl Send for DeferSink {}
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl Sync for DeferSink {}
+
+impl DeferSink {
+    /// Create a sink that runs the deferred futures with the given function
+    #[cfg(any(not(target_arch = "wasm32"), target_feature = "atomics"))]
+    pub fn new(sink: impl Fn(DeferredFuture) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(sink))
+    }
+
+    /// Create a sink that runs the deferred futures with the given function, like the `waitUntil` of the Worker
+    /// execution context
+    #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+    pub fn new(sink: impl Fn(DeferredFuture) + 'static) -> Self {
+        Self(Arc::new(sink))
+    }
+
+    /// Attach the sink to a request before it is passed to the router
+    pub fn attach<B>(self, request: &mut http::Request<B>) {
+        request.extensions_mut().insert(self);
+    }
+}
+
+/// Run a future in the background after the response is sent. This is useful for analytics writes, cache fills and
+/// webhooks that the client doesn't need to wait for. It can be called inside server functions and while rendering the
+/// app.
+///
+/// The future is passed to the [`DeferSink`] of the request. If there is no request, the future is dropped.
+///
+/// ```rust, no_run
+/// async fn create_post(title: String) -> Result<(), &'static str> {
+///     // save the post...
+///     dioxus_server::defer(async move {
+///         // notify subscribers about the new post
+///     });
+///     Ok(())
+/// }
+/// ```
+pub fn defer(future: impl Future<Output = ()> + 'static) {
+    match FullstackContext::current().and_then(|context| context.extension::<DeferSink>()) {
+        Some(sink) => (sink.0)(Box::pin(future)),
+        None => tracing::warn!("defer was called outside of a request, so the future was dropped"),
+    }
+}
+
+/// Holds back the futures deferred while handling a request until it is dropped
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct AfterResponse(#[allow(dead_code)] Option<tokio::sync::watch::Sender<()>>);
+
+/// Attach the default [`DeferSink`] to the request if the caller didn't attach one. Deferred futures are spawned on the
+/// current thread, but wait to start until the returned guard is dropped after the response is created. If a
+/// [`DeferredWork`] is attached to the request, it tracks the futures.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn defer_after_response(parts: &mut Parts) -> AfterResponse {
+    if parts.extensions.get::<DeferSink>().is_some() {
+        return AfterResponse(None);
+    }
+
+    let work = parts.extensions.get::<DeferredWork>().cloned();
+    let (response_created, receiver) = tokio::sync::watch::channel(());
+    parts.extensions.insert(DeferSink::new(move |future| {
+        let mut response_created = receiver.clone();
+        let pending = work.as_ref().map(DeferredWork::start);
+        tokio::task::spawn_local(async move {
+            // Nothing is sent on the channel, so this resolves once the guard is dropped
+            _ = response_created.changed().await;
+            future.await;
+            drop(pending);
+        });
+    }));
+    AfterResponse(Some(response_created))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) struct AfterResponse;
+
+/// Attach the default [`DeferSink`] to the request if the Worker entry point didn't attach one. Without `waitUntil`,
+/// the futures are spawned on the current executor and the runtime may stop them once the response is sent.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn defer_after_response(parts: &mut Parts) -> AfterResponse {
+    if parts.extensions.get::<DeferSink>().is_none() {
+        parts
+            .extensions
+            .insert(DeferSink::new(wasm_bindgen_futures::spawn_local));
+    }
+    AfterResponse
+}
+
+/// An in-process stand-in for `waitUntil` for tests. Attach it to a request with [`attach`](DeferredWork::attach), and
+/// then wait for the futures deferred while handling the request with [`finished`](DeferredWork::finished).
+///
+/// The futures still run the same way they do in production, on the thread that handled the request after the
+/// response is created. The stand-in only tracks them, so tests don't need to set up an executor.
+///
+/// ```rust, no_run
+/// use dioxus_server::DeferredWork;
+/// use tower::ServiceExt;
+///
+/// async fn call_server_function() {
+///     let work = DeferredWork::new();
+///     let mut request = http::Request::post("/api/create_post").body(axum::body::Body::empty()).unwrap();
+///     work.attach(&mut request);
+///     let response = dioxus_server::server_functions_router(None).oneshot(request).await.unwrap();
+///     work.finished().await;
+/// }
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Default)]
+pub struct DeferredWork {
+    pending: Arc<std::sync::atomic::AtomicUsize>,
+    finished: Arc<tokio::sync::Notify>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DeferredWork {
+    /// Create a new stand-in with no pending futures
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track the futures deferred while handling a request
+    pub fn attach<B>(&self, request: &mut http::Request<B>) {
+        request.extensions_mut().insert(self.clone());
+    }
+
+    /// The number of deferred futures that haven't finished yet
+    pub fn pending(&self) -> usize {
+        self.pending.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Wait until every deferred future has finished
+    pub async fn finished(&self) {
+        loop {
+            let notified = self.finished.notified();
+            let mut notified = std::pin::pin!(notified);
+            notified.as_mut().enable();
+            if self.pending() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Start tracking a deferred future. It is finished when the returned guard is dropped
+    fn start(&self) -> PendingWork {
+        self.pending
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        PendingWork(self.clone())
+    }
+}
+
+/// A deferred future tracked by a [`DeferredWork`]. The future also counts as finished if it is dropped before it
+/// completes, so [`DeferredWork::finished`] doesn't wait forever for work the runtime cancelled.
+#[cfg(not(target_arch = "wasm32"))]
+struct PendingWork(DeferredWork);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for PendingWork {
+    fn drop(&mut self) {
+        if self
+            .0
+            .pending
+            .fetch_sub(1, std::sync::atomic::Ordering::SeqCst)
+            == 1
+        {
+            self.0.finished.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{server_functions_router, ServerFunction};
+    use axum::{
+        body::Body,
+        extract::{Request, State},
+        response::{IntoResponse, Response},
+    };
+    use http::{Method, StatusCode};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+    use tower::ServiceExt;
+
+    /// The order things happened in while handling a request
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Events {
+        fn push(&self, event: &'static str) {
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn get(&self) -> Vec<&'static str> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn defer_work(
+        State(context): State<FullstackContext>,
+        _: Request,
+    ) -> Pin<Box<dyn Future<Output = Response>>> {
+        Box::pin(async move {
+            let events = context.extension::<Events>().unwrap();
+            let deferred = events.clone();
+            defer(async move {
+                tokio::task::yield_now().await;
+                deferred.push("deferred");
+            });
+            events.push("handler");
+            "created".into_response()
+        })
+    }
+
+    inventory::submit! {
+        ServerFunction::new(Method::POST, "/api/defer_work", || {
+            ServerFunction::make_handler(Method::POST, defer_work)
+        })
+    }
+
+    fn request() -> (http::Request<Body>, Events) {
+        let events = Events::default();
+        let mut request = http::Request::post("/api/defer_work")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(events.clone());
+        (request, events)
+    }
+
+    #[tokio::test]
+    async fn deferred_work_runs_after_the_response() {
+        let (mut request, events) = request();
+        let work = DeferredWork::new();
+        work.attach(&mut request);
+
+        let response = server_functions_router(None)
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        work.finished().await;
+        assert_eq!(work.pending(), 0);
+        assert_eq!(events.get(), ["handler", "deferred"]);
+    }
+
+    #[tokio::test]
+    async fn deferred_work_is_sent_to_the_attached_sink() {
+        let (mut request, events) = request();
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counter = sent.clone();
+        DeferSink::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .attach(&mut request);
+
+        let response = server_functions_router(None)
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The sink dropped the future without running it
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(events.get(), ["handler"]);
+    }
+
+    #[tokio::test]
+    async fn finished_returns_without_deferred_work() {
+        DeferredWork::new().finished().await;
+    }
+
+    #[test]
+    fn defer_outside_of_a_request_drops_the_future() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counter = ran.clone();
+        defer(async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+}
@@ -1,33 +1,182 @@
 //! On the server, we collect any elements that should be rendered into the head in the first frame of SSR.
-//! After the first frame, we have already sent down the head, so we can't modify it in place. The web client
-//! will hydrate the head with the correct contents once it loads.
+//! After the first frame, we have already sent down the head, so we can't modify it in place. Any changes after
+//! that point are queued and sent down as a script that patches the head along with the next resolved suspense
+//! boundary. Javascript evaluated during SSR is queued the same way and runs once the page loads.
 
 use std::cell::RefCell;
 
 use dioxus_core::Element;
 use dioxus_core_macro::rsx;
 use dioxus_document::{
-    Document, Eval, LinkProps, MetaProps, NoOpDocument, ScriptProps, StyleProps,
+    Document, Eval, EvalError, Evaluator, LinkProps, MetaProps, ScriptProps, StyleProps,
 };
 use dioxus_html as dioxus_elements;
 use dioxus_ssr::Renderer;
 use parking_lot::RwLock;
+use std::fmt::{Display, Write};
 use std::sync::LazyLock;
 
 static RENDERER: LazyLock<RwLock<Renderer>> = LazyLock::new(|| RwLock::new(Renderer::new()));
 
 /// Reset the static renderer to a fresh state, clearing its cache.
+#[cfg(not(target_arch = "wasm32"))]
 pub(crate) fn reset_renderer() {
     RENDERER.write().clear();
 }
 
+/// A per-request nonce for the `Content-Security-Policy` of a server rendered page.
+///
+/// When nonces are enabled in the [`ServeConfig`](crate::ServeConfig), the nonce is added to every script the
+/// server renders. It can be read inside components and server functions from the request extensions with
+/// [`FullstackContext::extension`](dioxus_fullstack_core::FullstackContext::extension).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    /// Generate a new random nonce
+    pub(crate) fn generate() -> Self {
+        use base64::Engine;
+
+        let mut bytes = [0u8; 16];
+        getrandom::fill(&mut bytes).expect("Failed to generate a random CSP nonce");
+        Self(base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+}
+
+/// The `nonce` attribute for a script tag the server renders. This writes nothing if nonces are disabled.
+pub(crate) struct NonceAttribute<'a>(pub(crate) Option<&'a str>);
+
+impl Display for NonceAttribute<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(nonce) => write!(f, r#" nonce="{nonce}""#),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Escape any `</script` in javascript that is inlined in a script tag. Html matches the end tag case-insensitively,
+/// so `</SCRIPT` would close the tag too. `<\/` is the same as `</` inside javascript strings, regexes and comments
+fn escape_script_end(js: &str) -> String {
+    let mut escaped = String::with_capacity(js.len());
+    let mut rest = js;
+    while let Some(start) = rest.find("</") {
+        let (before, after) = rest.split_at(start);
+        escaped.push_str(before);
+        let is_end_tag = after
+            .get(2..8)
+            .is_some_and(|tag| tag.eq_ignore_ascii_case("script"));
+        escaped.push_str(if is_end_tag { "<\\/" } else { "</" });
+        rest = &after[2..];
+    }
+    escaped.push_str(rest);
+    escaped
+}
+
+/// Applies head changes that were made after the head was streamed to the client. The arguments are the new title
+/// (or null) and the html of the new head elements. New meta and link elements replace the elements with the same key
+/// like they do on the server. Scripts parsed from html never run, so they are recreated before they are inserted.
+pub(crate) const HEAD_PATCH_JS: &str = r#"(function(t,h){function k(n){if(n.tagName=="META"){if(n.hasAttribute("charset"))return"meta[charset]";for(var a of["name","property","http-equiv"])if(n.hasAttribute(a))return"meta["+a+'="'+CSS.escape(n.getAttribute(a))+'"]'}if(n.tagName=="LINK"){var r=CSS.escape(n.getAttribute("rel")||"");if(r=="canonical")return'link[rel="canonical"]';if(n.hasAttribute("href"))return'link[rel="'+r+'"][href="'+CSS.escape(n.getAttribute("href"))+'"]'}}if(t!==null)document.title=t;var e=document.createElement("template");e.innerHTML=h;Array.from(e.content.childNodes).forEach(function(n){var s=k(n);if(s)document.head.querySelectorAll(s).forEach(function(o){o.remove()});if(n.tagName=="SCRIPT"){var c=document.createElement("script");for(var i=0;i<n.attributes.length;i++)c.setAttribute(n.attributes[i].name,n.attributes[i].value);c.textContent=n.textContent;n=c}document.head.appendChild(n)})})"#;
+
+/// Encode a string as a javascript string literal that is safe to inline in a script tag
+fn js_string(value: &str) -> String {
+    serde_json::to_string(value)
+        .unwrap_or_default()
+        .replace('<', "\\u003c")
+}
+
 #[derive(Default)]
 struct ServerDocumentInner {
     streaming: bool,
+    nonce: Option<String>,
     title: Option<String>,
-    meta: Vec<Element>,
-    link: Vec<Element>,
-    script: Vec<Element>,
+    /// The title set after the head was streamed to the client
+    late_title: Option<String>,
+    /// The html of head elements created after the head was streamed to the client
+    late_head: String,
+    /// Javascript evaluated during SSR
+    evals: Vec<String>,
+    /// The number of evals that were already sent to the client
+    evals_sent: usize,
+    /// The owner of the evaluators returned from `eval`
+    eval_owner: generational_box::Owner,
+    /// `Link` header values for the `preload`, `modulepreload` and `preconnect` links in the head
+    preload_links: Vec<String>,
+    charset: HeadElements,
+    meta: HeadElements,
+    link: HeadElements,
+    style: HeadElements,
+    script: HeadElements,
+}
+
+/// One kind of head element in the order they were created. An element with a key replaces the earlier element
+/// with the same key, so the last component to set it wins.
+#[derive(Default)]
+struct HeadElements(Vec<(Option<String>, Element)>);
+
+impl HeadElements {
+    fn insert(&mut self, key: Option<String>, element: Element) {
+        let existing = key
+            .as_ref()
+            .and_then(|key| self.0.iter_mut().find(|(k, _)| k.as_ref() == Some(key)));
+        match existing {
+            Some((_, existing)) => *existing = element,
+            None => self.0.push((key, element)),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Element> {
+        self.0.iter().map(|(_, element)| element)
+    }
+}
+
+/// The key of a meta element. Meta elements are keyed by the name, property, or http-equiv they describe
+fn meta_key(props: &MetaProps) -> Option<String> {
+    if let Some(name) = &props.name {
+        Some(format!("name={name}"))
+    } else if let Some(property) = &props.property {
+        Some(format!("property={property}"))
+    } else {
+        props
+            .http_equiv
+            .as_ref()
+            .map(|http_equiv| format!("http-equiv={http_equiv}"))
+    }
+}
+
+/// The `Link` header value for a link that preloads or preconnects to a resource
+fn preload_link(props: &LinkProps) -> Option<String> {
+    let rel = props.rel.as_deref()?;
+    if !matches!(rel, "preload" | "modulepreload" | "preconnect") {
+        return None;
+    }
+
+    let mut link = format!("<{}>; rel={rel}", props.href.as_deref()?);
+    if let Some(r#as) = &props.r#as {
+        _ = write!(link, "; as={as}");
+    }
+    if let Some(r#type) = &props.r#type {
+        _ = write!(link, r#"; type="{type}""#);
+    }
+    match props.crossorigin.as_deref() {
+        Some("") => link.push_str("; crossorigin"),
+        Some(crossorigin) => _ = write!(link, "; crossorigin={crossorigin}"),
+        None => {}
+    }
+    if let Some(fetchpriority) = &props.fetchpriority {
+        _ = write!(link, "; fetchpriority={fetchpriority}");
+    }
+    Some(link)
+}
+
+/// The key of a link element. Links are keyed by their rel and href, except the canonical link which there can
+/// only be one of
+fn link_key(props: &LinkProps) -> Option<String> {
+    let rel = props.rel.as_deref().unwrap_or_default();
+    if rel == "canonical" {
+        return Some(rel.to_string());
+    }
+    props.href.as_ref().map(|href| format!("{rel} {href}"))
 }
 
 /// A Document provider that collects all contents injected into the head for SSR rendering.
@@ -35,6 +184,19 @@ struct ServerDocumentInner {
 pub struct ServerDocument(RefCell<ServerDocumentInner>);
 
 impl ServerDocument {
+    /// Create a document that adds the nonce to any scripts and styles that don't set one
+    pub(crate) fn with_nonce(nonce: Option<String>) -> Self {
+        Self(RefCell::new(ServerDocumentInner {
+            nonce,
+            ..Default::default()
+        }))
+    }
+
+    /// The `Content-Security-Policy` nonce for the current request if nonces are enabled
+    pub fn nonce(&self) -> Option<String> {
+        self.0.borrow().nonce.clone()
+    }
+
     pub(crate) fn title(&self) -> Option<String> {
         let myself = self.0.borrow();
         myself.title.as_ref().map(|title| {
@@ -44,11 +206,21 @@ impl ServerDocument {
         })
     }
 
+    /// A script that sets the title of the page if the app set one
+    pub(crate) fn title_script(&self) -> Option<String> {
+        let myself = self.0.borrow();
+        let title = myself.title.as_deref()?;
+        Some(format!("document.title={};", js_string(title)))
+    }
+
+    /// Render the head elements in a fixed order: the charset first, then meta, link, style and script elements
     pub(crate) fn render(&self, to: &mut impl std::fmt::Write) -> std::fmt::Result {
         let myself = self.0.borrow();
         let element = rsx! {
+            {myself.charset.iter().map(|c| rsx! { {c} })}
             {myself.meta.iter().map(|m| rsx! { {m} })}
             {myself.link.iter().map(|l| rsx! { {l} })}
+            {myself.style.iter().map(|s| rsx! { {s} })}
             {myself.script.iter().map(|s| rsx! { {s} })}
         };
 
@@ -57,110 +229,379 @@ impl ServerDocument {
         Ok(())
     }
 
+    /// `Link` header values for the `preload`, `modulepreload` and `preconnect` links in the head
+    pub(crate) fn preload_links(&self) -> Vec<String> {
+        self.0.borrow().preload_links.clone()
+    }
+
+    /// Mark the head as sent to the client. Any changes after this point are queued as head patches
     pub(crate) fn start_streaming(&self) {
         self.0.borrow_mut().streaming = true;
     }
 
-    pub(crate) fn warn_if_streaming(&self) {
-        if self.0.borrow().streaming {
-            tracing::warn!("Attempted to insert content into the head after the initial streaming frame. Inserting content into the head only works during the initial render of SSR outside before resolving any suspense boundaries.");
+    /// Add an element to the head, replacing any earlier element with the same key. If the head was already
+    /// streamed to the client, the element is also queued for the next head patch. The element stays in the document
+    /// so it is included if the page is cached.
+    fn insert_head_element(
+        &self,
+        key: Option<String>,
+        element: Element,
+        bucket: impl FnOnce(&mut ServerDocumentInner) -> &mut HeadElements,
+    ) {
+        let mut myself = self.0.borrow_mut();
+        if myself.streaming {
+            let html = RENDERER.write().render_element(element.clone());
+            myself.late_head.push_str(&html);
+        }
+        bucket(&mut myself).insert(key, element);
+    }
+
+    /// Take the head changes made since the last patch as javascript that applies them to the head in the client
+    pub(crate) fn take_head_patch(&self) -> Option<String> {
+        let mut myself = self.0.borrow_mut();
+        let title = myself.late_title.take();
+        let html = std::mem::take(&mut myself.late_head);
+        if title.is_none() && html.is_empty() {
+            return None;
         }
+
+        let title = title
+            .as_deref()
+            .map_or_else(|| "null".to_string(), js_string);
+        Some(format!("{HEAD_PATCH_JS}({title},{})", js_string(&html)))
+    }
+
+    /// Take the javascript evaluated since the last call as a script that runs each eval in order
+    pub(crate) fn take_eval_script(&self) -> Option<String> {
+        let mut myself = self.0.borrow_mut();
+        let pending = &myself.evals[myself.evals_sent..];
+        if pending.is_empty() {
+            return None;
+        }
+
+        // Each eval runs in its own async function like it does in the client, so one failing eval doesn't stop
+        // the others
+        let script = pending
+            .iter()
+            .map(|js| {
+                format!(
+                    "(async function(){{{}\n}})().catch(console.error);",
+                    escape_script_end(js)
+                )
+            })
+            .collect();
+        myself.evals_sent = myself.evals.len();
+        Some(script)
+    }
+
+    /// Queue every eval again so a full render of the page, like the one stored in the incremental cache, includes
+    /// all of them
+    pub(crate) fn replay_evals(&self) {
+        self.0.borrow_mut().evals_sent = 0;
     }
 
     /// Write the head element into the serialized context for hydration
-    /// We write true if the head element was written to the DOM during server side rendering
+    /// We write true because the head element is written to the DOM during server side rendering, either in the
+    /// initial head or in a head patch while streaming
     #[track_caller]
     pub(crate) fn serialize_for_hydration(&self) {
         // We only serialize the head elements if the web document feature is enabled
         #[cfg(feature = "document")]
         {
             dioxus_fullstack_core::head_element_hydration_entry()
-                .insert(&!self.0.borrow().streaming, std::panic::Location::caller());
+                .insert(&true, std::panic::Location::caller());
         }
     }
 }
 
 impl Document for ServerDocument {
+    /// Queue the javascript to run in the client once the page loads. There is no javascript runtime on the server, so
+    /// the returned eval fails immediately instead of waiting for a result
     fn eval(&self, js: String) -> Eval {
-        NoOpDocument.eval(js)
+        struct ServerEvaluator;
+        impl Evaluator for ServerEvaluator {
+            fn send(&self, _: serde_json::Value) -> Result<(), EvalError> {
+                Err(Self::not_available())
+            }
+
+            fn poll_recv(
+                &mut self,
+                _: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<Result<serde_json::Value, EvalError>> {
+                std::task::Poll::Ready(Err(Self::not_available()))
+            }
+
+            fn poll_join(
+                &mut self,
+                _: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<Result<serde_json::Value, EvalError>> {
+                std::task::Poll::Ready(Err(Self::not_available()))
+            }
+        }
+        impl ServerEvaluator {
+            fn not_available() -> EvalError {
+                EvalError::Communication(
+                    "eval is not available on the server. The javascript will run in the client once the page loads, but its result can't be read during SSR".to_string(),
+                )
+            }
+        }
+
+        let mut myself = self.0.borrow_mut();
+        myself.evals.push(js);
+        Eval::new(myself.eval_owner.insert(Box::new(ServerEvaluator)))
     }
 
     fn set_title(&self, title: String) {
-        self.warn_if_streaming();
-        self.0.borrow_mut().title = Some(title);
+        let mut myself = self.0.borrow_mut();
+        if myself.streaming {
+            myself.late_title = Some(title.clone());
+        }
+        myself.title = Some(title);
     }
 
     fn create_meta(&self, props: MetaProps) {
-        self.0.borrow_mut().meta.push(rsx! {
-            meta {
-                name: props.name,
-                charset: props.charset,
-                http_equiv: props.http_equiv,
-                content: props.content,
-                property: props.property,
-                "data": props.data,
-                ..props.additional_attributes,
-            }
-        });
+        // There can only be one charset, so it is kept separately from the other meta elements
+        let is_charset = props.charset.is_some();
+        let key = if is_charset {
+            Some("charset".to_string())
+        } else {
+            meta_key(&props)
+        };
+        self.insert_head_element(
+            key,
+            rsx! {
+                meta {
+                    name: props.name,
+                    charset: props.charset,
+                    http_equiv: props.http_equiv,
+                    content: props.content,
+                    property: props.property,
+                    "data": props.data,
+                    ..props.additional_attributes,
+                }
+            },
+            |head| {
+                if is_charset {
+                    &mut head.charset
+                } else {
+                    &mut head.meta
+                }
+            },
+        );
     }
 
     fn create_script(&self, props: ScriptProps) {
         let children = props.script_contents().ok();
-        self.0.borrow_mut().script.push(rsx! {
-            script {
-                src: props.src,
-                defer: props.defer,
-                crossorigin: props.crossorigin,
-                fetchpriority: props.fetchpriority,
-                integrity: props.integrity,
-                nomodule: props.nomodule,
-                nonce: props.nonce,
-                referrerpolicy: props.referrerpolicy,
-                r#type: props.r#type,
-                ..props.additional_attributes,
-                {children}
-            }
-        });
+        let nonce = props.nonce.or_else(|| self.nonce());
+        self.insert_head_element(
+            None,
+            rsx! {
+                script {
+                    src: props.src,
+                    defer: props.defer,
+                    crossorigin: props.crossorigin,
+                    fetchpriority: props.fetchpriority,
+                    integrity: props.integrity,
+                    nomodule: props.nomodule,
+                    nonce,
+                    referrerpolicy: props.referrerpolicy,
+                    r#type: props.r#type,
+                    ..props.additional_attributes,
+                    {children}
+                }
+            },
+            |head| &mut head.script,
+        );
     }
 
     fn create_style(&self, props: StyleProps) {
         let contents = props.style_contents().ok();
-        self.0.borrow_mut().script.push(rsx! {
-            style {
-                media: props.media,
-                nonce: props.nonce,
-                title: props.title,
-                ..props.additional_attributes,
-                {contents}
-            }
-        })
+        let nonce = props.nonce.or_else(|| self.nonce());
+        self.insert_head_element(
+            None,
+            rsx! {
+                style {
+                    media: props.media,
+                    nonce,
+                    title: props.title,
+                    ..props.additional_attributes,
+                    {contents}
+                }
+            },
+            |head| &mut head.style,
+        )
     }
 
     fn create_link(&self, props: LinkProps) {
-        self.0.borrow_mut().link.push(rsx! {
-            link {
-                rel: props.rel,
-                media: props.media,
-                title: props.title,
-                disabled: props.disabled,
-                r#as: props.r#as,
-                sizes: props.sizes,
-                href: props.href,
-                crossorigin: props.crossorigin,
-                referrerpolicy: props.referrerpolicy,
-                fetchpriority: props.fetchpriority,
-                hreflang: props.hreflang,
-                integrity: props.integrity,
-                r#type: props.r#type,
-                blocking: props.blocking,
-                ..props.additional_attributes,
+        if let Some(link) = preload_link(&props) {
+            let mut myself = self.0.borrow_mut();
+            if !myself.preload_links.contains(&link) {
+                myself.preload_links.push(link);
             }
-        })
+        }
+        self.insert_head_element(
+            link_key(&props),
+            rsx! {
+                link {
+                    rel: props.rel,
+                    media: props.media,
+                    title: props.title,
+                    disabled: props.disabled,
+                    r#as: props.r#as,
+                    sizes: props.sizes,
+                    href: props.href,
+                    crossorigin: props.crossorigin,
+                    referrerpolicy: props.referrerpolicy,
+                    fetchpriority: props.fetchpriority,
+                    hreflang: props.hreflang,
+                    integrity: props.integrity,
+                    r#type: props.r#type,
+                    blocking: props.blocking,
+                    ..props.additional_attributes,
+                }
+            },
+            |head| &mut head.link,
+        )
     }
 
     fn create_head_component(&self) -> bool {
-        self.warn_if_streaming();
         self.serialize_for_hydration();
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_script(evals: &[&str]) -> String {
+        let document = ServerDocument::default();
+        document
+            .0
+            .borrow_mut()
+            .evals
+            .extend(evals.iter().map(|js| js.to_string()));
+        document.take_eval_script().unwrap()
+    }
+
+    #[test]
+    fn eval_scripts_escape_script_end_tags_in_any_case() {
+        let script = eval_script(&[
+            r#"console.log("</script><script>alert(1)</script>")"#,
+            r#"console.log("</SCRIPT>", "</sCrIpT >", "</scrip")"#,
+        ]);
+        assert!(!script.to_ascii_lowercase().contains("</script"));
+        assert!(script.contains(r#"console.log("<\/script><script>alert(1)<\/script>")"#));
+        assert!(script.contains(r#"console.log("<\/SCRIPT>", "<\/sCrIpT >", "</scrip")"#));
+    }
+
+    #[test]
+    fn eval_scripts_keep_other_end_tags() {
+        let script = eval_script(&[r#"document.body.innerHTML = "<p>hi</p>""#]);
+        assert!(script.contains(r#"document.body.innerHTML = "<p>hi</p>""#));
+    }
+
+    fn meta(name: &str, content: &str) -> MetaProps {
+        MetaProps::builder()
+            .name(name.to_string())
+            .content(content.to_string())
+            .build()
+    }
+
+    fn link(rel: &str, href: &str, title: &str) -> LinkProps {
+        LinkProps::builder()
+            .rel(rel.to_string())
+            .href(href.to_string())
+            .title(title.to_string())
+            .build()
+    }
+
+    fn rendered_head(document: &ServerDocument) -> String {
+        let mut head = String::new();
+        document.render(&mut head).unwrap();
+        head
+    }
+
+    #[test]
+    fn repeated_head_elements_keep_the_last_value() {
+        let document = ServerDocument::default();
+        document.create_meta(meta("description", "first"));
+        document.create_meta(meta("author", "someone"));
+        document.create_meta(meta("description", "second"));
+        document.create_meta(MetaProps::builder().charset("latin1".to_string()).build());
+        document.create_meta(MetaProps::builder().charset("utf-8".to_string()).build());
+        document.create_link(link("icon", "/favicon.ico", "first"));
+        document.create_link(link("icon", "/favicon.ico", "second"));
+        document.create_link(link("icon", "/other.ico", "other"));
+
+        let head = rendered_head(&document);
+        assert_eq!(head.matches("<meta").count(), 3, "{head}");
+        assert_eq!(head.matches("<link").count(), 2, "{head}");
+        assert!(
+            !head.contains("first") && !head.contains("latin1"),
+            "{head}"
+        );
+        assert!(head.contains(r#"charset="utf-8""#), "{head}");
+
+        // The replaced element keeps the position of the element it replaced
+        let description = head.find(r#"content="second""#).unwrap();
+        let author = head.find(r#"content="someone""#).unwrap();
+        assert!(description < author, "{head}");
+        let icon = head.find(r#"title="second""#).unwrap();
+        let other = head.find(r#"title="other""#).unwrap();
+        assert!(icon < other, "{head}");
+    }
+
+    #[test]
+    fn head_element_keys() {
+        assert_eq!(
+            meta_key(&meta("description", "a")),
+            meta_key(&meta("description", "b"))
+        );
+        assert_ne!(
+            meta_key(&meta("description", "a")),
+            meta_key(
+                &MetaProps::builder()
+                    .property("description".to_string())
+                    .build()
+            )
+        );
+        assert_eq!(meta_key(&MetaProps::builder().build()), None);
+
+        assert_eq!(
+            link_key(&link("canonical", "/a", "a")),
+            link_key(&link("canonical", "/b", "b"))
+        );
+        assert_ne!(
+            link_key(&link("icon", "/a", "a")),
+            link_key(&link("preload", "/a", "a"))
+        );
+        assert_eq!(
+            link_key(&LinkProps::builder().rel("icon".to_string()).build()),
+            None
+        );
+    }
+
+    #[test]
+    fn head_elements_are_rendered_by_kind() {
+        let document = ServerDocument::default();
+        document.create_script(ScriptProps::builder().src("/app.js".to_string()).build());
+        document.create_style(StyleProps::builder().media("print".to_string()).build());
+        document.create_link(link("stylesheet", "/app.css", "styles"));
+        document.create_meta(meta("description", "page"));
+        document.create_meta(MetaProps::builder().charset("utf-8".to_string()).build());
+
+        let head = rendered_head(&document);
+        let positions = ["<meta charset", "<meta name", "<link", "<style", "<script"]
+            .map(|tag| head.find(tag).unwrap_or_else(|| panic!("{tag} in {head}")));
+        assert!(positions.is_sorted(), "{head}");
+    }
+
+    #[test]
+    fn eval_scripts_are_only_sent_once() {
+        let document = ServerDocument::default();
+        document.0.borrow_mut().evals.push("one()".to_string());
+        assert!(document.take_eval_script().unwrap().contains("one()"));
+        assert_eq!(document.take_eval_script(), None);
+    }
+}
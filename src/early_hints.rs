@@ -0,0 +1,185 @@
+//! `103 Early Hints` responses for the native server.
+//!
+//! hyper only lets a service return one response, so informational responses can't go through it. Instead, the
+//! launch server wraps each connection in an [`EarlyHintsIo`] and gives the render handler an [`EarlyHints`] handle
+//! that writes the `103` response straight to the socket before the page is rendered.
+use http::HeaderValue;
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::{ready, Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+/// A TCP connection that hyper serves while the render handler can still send early hints on it
+pub(crate) struct EarlyHintsIo(Arc<TcpStream>);
+
+impl EarlyHintsIo {
+    /// Wrap a connection and create the handle that sends early hints on it
+    pub(crate) fn new(stream: TcpStream) -> (Self, EarlyHints) {
+        let stream = Arc::new(stream);
+        let hints = EarlyHints(Arc::downgrade(&stream));
+        (Self(stream), hints)
+    }
+}
+
+impl AsyncRead for EarlyHintsIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.0.poll_read_ready(cx))?;
+            match self.0.try_read(buf.initialize_unfilled()) {
+                Ok(read) => {
+                    buf.advance(read);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for EarlyHintsIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.0.poll_write_ready(cx))?;
+            match self.0.try_write(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Writes go straight to the socket
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // The socket is closed once hyper drops the connection. The handle only holds a weak reference, so it doesn't
+        // keep the socket open
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Sends `103 Early Hints` on the connection a request arrived on. The launch server adds it to the request extensions
+/// of every request.
+#[derive(Clone)]
+pub(crate) struct EarlyHints(Weak<TcpStream>);
+
+impl EarlyHints {
+    /// Send a `103 Early Hints` response with a `Link` header for each link.
+    ///
+    /// This must be called before the handler returns a response, so nothing hyper wrote for the request can be
+    /// interleaved with the hints. Only HTTP/1.1 clients can receive informational responses.
+    pub(crate) async fn send(&self, links: &[String]) -> io::Result<()> {
+        let Some(stream) = self.0.upgrade() else {
+            return Ok(());
+        };
+
+        let mut response = String::from("HTTP/1.1 103 Early Hints\r\n");
+        for link in links {
+            // Skip links that would break the response head
+            if HeaderValue::from_str(link).is_ok() {
+                response.push_str("link: ");
+                response.push_str(link);
+                response.push_str("\r\n");
+            }
+        }
+        response.push_str("\r\n");
+
+        let mut bytes = response.as_bytes();
+        while !bytes.is_empty() {
+            stream.writable().await?;
+            match stream.try_write(bytes) {
+                Ok(written) => bytes = &bytes[written..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{launch::serve_connection, FullstackState, IndexHtml, ServeConfig};
+    use axum::{routing::get, Router};
+    use dioxus_core::{Element, VNode};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    fn app() -> Element {
+        VNode::empty()
+    }
+
+    /// Serve one connection with early hints enabled and return the raw bytes the server sent for the request
+    async fn request(early_hints: bool, request: &str) -> String {
+        let index = IndexHtml::new(
+            r#"<html><head><script type="module" src="/app.js"></script></head><body><div id="main"></div></body></html>"#,
+            "main",
+        )
+        .unwrap();
+        let cfg = ServeConfig::with_index_html(index).early_hints(early_hints);
+        let router = Router::new()
+            .route("/", get(FullstackState::render_handler))
+            .with_state(FullstackState::new(cfg, app));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::task::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            _ = serve_connection(tcp_stream, router.into_make_service()).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        String::from_utf8(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn early_hints_are_sent_before_the_page() {
+        let response = request(
+            true,
+            "GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+        )
+        .await;
+        let (hints, page) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            hints,
+            "HTTP/1.1 103 Early Hints\r\nlink: </app.js>; rel=modulepreload"
+        );
+        assert!(page.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(page.contains(r#"<div id="main">"#));
+    }
+
+    #[tokio::test]
+    async fn early_hints_are_only_sent_when_enabled_to_http_1_1_clients() {
+        let response = request(
+            false,
+            "GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let response = request(true, "GET / HTTP/1.0\r\nhost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(!response.contains("103 Early Hints"));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::document::NonceAttribute;
 use anyhow::Context;
 use std::path::Path;
 
@@ -29,12 +30,14 @@ use std::path::Path;
 /// ```
 #[derive(Clone, Debug)]
 pub struct IndexHtml {
-    pub(crate) head_before_title: String,
-    pub(crate) head_after_title: String,
+    pub(crate) head_before_title: ShellSection,
+    pub(crate) head_after_title: ShellSection,
     pub(crate) title: String,
-    pub(crate) close_head: String,
-    pub(crate) post_main: String,
-    pub(crate) after_closing_body_tag: String,
+    pub(crate) close_head: ShellSection,
+    pub(crate) post_main: ShellSection,
+    pub(crate) after_closing_body_tag: ShellSection,
+    /// `Link` header values that preload the JS and wasm bundle the page loads
+    pub(crate) preload_links: Vec<String>,
 }
 
 impl IndexHtml {
@@ -81,12 +84,15 @@ impl IndexHtml {
         }
 
         Ok(IndexHtml {
-            head_before_title,
-            head_after_title,
+            preload_links: bundle_preload_links(contents),
+            head_before_title: ShellSection::new(head_before_title),
+            head_after_title: ShellSection::new(head_after_title),
             title,
-            close_head,
-            post_main: post_main.to_string(),
-            after_closing_body_tag: "</body>".to_string() + after_closing_body_tag,
+            close_head: ShellSection::new(close_head),
+            post_main: ShellSection::new(post_main.to_string()),
+            after_closing_body_tag: ShellSection::new(
+                "</body>".to_string() + after_closing_body_tag,
+            ),
         })
     }
 
@@ -114,3 +120,281 @@ impl IndexHtml {
         Self::new(DEFAULT, "main").expect("Failed to load default index.html")
     }
 }
+
+/// A section of the `index.html` shell. The script elements in the section are found when the shell is parsed, so the
+/// nonce of each request is only added to the start tag of scripts that don't already set one.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ShellSection {
+    html: String,
+    /// The byte offsets right after the `<script` of each start tag that needs a nonce
+    nonce_offsets: Vec<usize>,
+}
+
+impl ShellSection {
+    fn new(html: String) -> Self {
+        let nonce_offsets = script_tags_without_nonce(&html);
+        Self {
+            html,
+            nonce_offsets,
+        }
+    }
+
+    /// Write the section, adding the nonce to the scripts in it if nonces are enabled
+    pub(crate) fn write_with_nonce(
+        &self,
+        to: &mut (impl std::fmt::Write + ?Sized),
+        nonce: Option<&str>,
+    ) -> std::fmt::Result {
+        if nonce.is_none() {
+            return to.write_str(&self.html);
+        }
+        let mut written = 0;
+        for &offset in &self.nonce_offsets {
+            to.write_str(&self.html[written..offset])?;
+            write!(to, "{}", NonceAttribute(nonce))?;
+            written = offset;
+        }
+        to.write_str(&self.html[written..])
+    }
+}
+
+/// Find the script start tags in some html that don't have a `nonce` attribute. Comments, attribute values and the
+/// text of raw text elements like scripts and styles are skipped, so a `<script` inside them is left as it is.
+fn script_tags_without_nonce(html: &str) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut index = 0;
+    while let Some(start) = html[index..].find('<').map(|start| index + start) {
+        let rest = &html[start + 1..];
+        if rest.starts_with("!--") {
+            index = rest
+                .find("-->")
+                .map_or(html.len(), |end| start + 1 + end + "-->".len());
+            continue;
+        }
+
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .unwrap_or(rest.len());
+        if name_len == 0 || !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            index = start + 1;
+            continue;
+        }
+        let name = &rest[..name_len];
+        let (has_nonce, tag_len) = read_attributes(&rest[name_len..]);
+        index = start + 1 + name_len + tag_len;
+
+        if name.eq_ignore_ascii_case("script") && !has_nonce {
+            offsets.push(start + 1 + name_len);
+        }
+
+        // The text inside raw text elements is never parsed as tags
+        let is_raw_text = ["script", "style", "textarea", "title"]
+            .iter()
+            .any(|raw| name.eq_ignore_ascii_case(raw));
+        if is_raw_text {
+            index = find_end_tag(html, index, name).unwrap_or(html.len());
+        }
+    }
+    offsets
+}
+
+/// Read the attributes after the name of a start tag. Returns if the tag has a `nonce` attribute and the length of the
+/// attributes up to and including the closing `>`
+fn read_attributes(tag: &str) -> (bool, usize) {
+    let mut has_nonce = false;
+    let mut chars = tag.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '>' => return (has_nonce, index + 1),
+            c if c.is_ascii_whitespace() || c == '/' => {}
+            _ => {
+                // Read the attribute name
+                let mut end = tag.len();
+                while let Some(&(index, c)) = chars.peek() {
+                    if c.is_ascii_whitespace() || matches!(c, '=' | '>' | '/') {
+                        end = index;
+                        break;
+                    }
+                    chars.next();
+                }
+                has_nonce |= tag[index..end].eq_ignore_ascii_case("nonce");
+
+                // Skip the value if there is one
+                while chars.next_if(|(_, c)| c.is_ascii_whitespace()).is_some() {}
+                if chars.next_if(|(_, c)| *c == '=').is_none() {
+                    continue;
+                }
+                while chars.next_if(|(_, c)| c.is_ascii_whitespace()).is_some() {}
+                match chars.peek().map(|&(_, c)| c) {
+                    Some(quote @ ('"' | '\'')) => {
+                        chars.next();
+                        for (_, c) in chars.by_ref() {
+                            if c == quote {
+                                break;
+                            }
+                        }
+                    }
+                    _ => {
+                        while chars
+                            .next_if(|(_, c)| !c.is_ascii_whitespace() && *c != '>')
+                            .is_some()
+                        {}
+                    }
+                }
+            }
+        }
+    }
+    (has_nonce, tag.len())
+}
+
+/// Find the start of the end tag of a raw text element, matching the tag name case-insensitively
+fn find_end_tag(html: &str, from: usize, name: &str) -> Option<usize> {
+    let mut index = from;
+    while let Some(start) = html[index..].find("</").map(|start| index + start) {
+        let tag = &html[start + 2..];
+        let is_end_tag = tag
+            .get(..name.len())
+            .is_some_and(|tag| tag.eq_ignore_ascii_case(name))
+            && tag[name.len()..]
+                .starts_with(|c: char| c.is_ascii_whitespace() || matches!(c, '>' | '/'));
+        if is_end_tag {
+            return Some(start);
+        }
+        index = start + 2;
+    }
+    None
+}
+
+/// Find the JS and wasm bundle the page loads and create `Link` header values that preload them. This looks for
+/// scripts with a `src` attribute, module imports inside inline scripts, and any quoted `.wasm` path.
+fn bundle_preload_links(contents: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut push = |link: String| {
+        if !links.contains(&link) {
+            links.push(link);
+        }
+    };
+
+    for script in contents.split("<script").skip(1) {
+        let Some((tag, body)) = script.split_once('>') else {
+            continue;
+        };
+        let body = body.split("</script>").next().unwrap_or_default();
+        let is_module = tag.contains(r#"type="module""#);
+
+        if let Some(src) = attribute_value(tag, "src") {
+            push(if is_module {
+                format!("<{src}>; rel=modulepreload")
+            } else {
+                format!("<{src}>; rel=preload; as=script")
+            });
+        }
+
+        let imports = body
+            .match_indices("from ")
+            .chain(body.match_indices("import("));
+        for (index, pattern) in imports {
+            if let Some(path) = quoted_prefix(&body[index + pattern.len()..]) {
+                if path.ends_with(".js") {
+                    push(format!("<{path}>; rel=modulepreload"));
+                }
+            }
+        }
+    }
+
+    for (index, _) in contents.match_indices(".wasm") {
+        let end = index + ".wasm".len();
+        let Some(quote) = contents[end..]
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '"' | '\''))
+        else {
+            continue;
+        };
+        let Some(start) = contents[..index].rfind(quote) else {
+            continue;
+        };
+        let path = &contents[start + 1..end];
+        if !path.contains(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '(' | ')')) {
+            push(format!(
+                r#"<{path}>; rel=preload; as=fetch; type="application/wasm"; crossorigin"#
+            ));
+        }
+    }
+
+    links
+}
+
+/// Read the value of a double quoted attribute in the inside of an html tag
+fn attribute_value<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let (_, value) = tag.split_once(&format!(r#"{name}=""#))?;
+    value.split('"').next()
+}
+
+/// Read a quoted string at the start of some javascript
+fn quoted_prefix(js: &str) -> Option<&str> {
+    let js = js.trim_start();
+    let quote = js.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+    js[1..].split(quote).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_nonce(html: &str) -> String {
+        let mut out = String::new();
+        ShellSection::new(html.to_string())
+            .write_with_nonce(&mut out, Some("abc"))
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn nonces_are_added_to_script_start_tags() {
+        assert_eq!(
+            with_nonce(r#"<script src="/app.js"></script><SCRIPT type="module">go()</SCRIPT>"#),
+            r#"<script nonce="abc" src="/app.js"></script><SCRIPT nonce="abc" type="module">go()</SCRIPT>"#
+        );
+        assert_eq!(with_nonce("<script>"), r#"<script nonce="abc">"#);
+    }
+
+    #[test]
+    fn scripts_that_set_a_nonce_keep_it() {
+        let html =
+            r#"<script nonce="static" src="/a.js"></script><script async NONCE=other></script>"#;
+        assert_eq!(with_nonce(html), html);
+    }
+
+    #[test]
+    fn script_text_outside_of_tags_is_left_as_it_is() {
+        let html = concat!(
+            r#"<meta name="description" content="<script> tags">"#,
+            "<!-- <script src=/old.js></script> -->",
+            "<title>Using <script></title>",
+            "<noscriptx><scripts>",
+            r#"<style>p::after { content: "<script>" }</style>"#,
+        );
+        assert_eq!(with_nonce(html), html);
+    }
+
+    #[test]
+    fn script_text_inside_scripts_is_left_as_it_is() {
+        assert_eq!(
+            with_nonce(
+                r#"<script>document.write("<script src=/b.js></" + "script>")</script><script></script>"#
+            ),
+            r#"<script nonce="abc">document.write("<script src=/b.js></" + "script>")</script><script nonce="abc"></script>"#
+        );
+    }
+
+    #[test]
+    fn sections_are_unchanged_without_a_nonce() {
+        let html = r#"<script src="/app.js"></script>"#;
+        let mut out = String::new();
+        ShellSection::new(html.to_string())
+            .write_with_nonce(&mut out, None)
+            .unwrap();
+        assert_eq!(out, html);
+    }
+}
@@ -14,6 +14,8 @@ use std::{
 /// A configuration for the incremental renderer.
 #[derive(Clone)]
 pub struct IncrementalRendererConfig {
+    // There is no filesystem tier on wasm32, so only the memory cache is used
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
     static_dir: PathBuf,
     memory_cache_limit: usize,
     invalidate_after: Option<Duration>,
@@ -58,7 +60,7 @@ impl IncrementalRendererConfig {
         self
     }
 
-    /// Set the static directory.
+    /// Set the static directory. On wasm32, rendered routes are only cached in memory and this is ignored.
     pub fn static_dir<P: AsRef<Path>>(mut self, static_dir: P) -> Self {
         self.static_dir = static_dir.as_ref().to_path_buf();
         self
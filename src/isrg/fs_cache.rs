@@ -5,6 +5,9 @@ use chrono::{DateTime, Utc};
 use super::{IncrementalRendererError, RenderFreshness};
 use std::{path::PathBuf, sync::Arc, time::SystemTime};
 
+/// The extension of the file the `Link` preload header values of a cached render are stored in
+const LINKS_EXTENSION: &str = "links";
+
 pub(crate) type PathMapFn = Arc<dyn Fn(&str) -> PathBuf + Send + Sync>;
 
 pub(crate) struct FileSystemCache {
@@ -40,6 +43,7 @@ impl FileSystemCache {
         route: String,
         timestamp: DateTime<Utc>,
         data: Vec<u8>,
+        preload_links: &[String],
     ) -> Result<(), IncrementalRendererError> {
         use std::io::Write;
         let file_path = self.route_as_path(&route, timestamp);
@@ -48,6 +52,13 @@ impl FileSystemCache {
                 std::fs::create_dir_all(parent)?;
             }
         }
+        // The preload links are stored next to the html, one per line
+        let links_path = file_path.with_extension(LINKS_EXTENSION);
+        if preload_links.is_empty() {
+            _ = std::fs::remove_file(links_path);
+        } else {
+            std::fs::write(links_path, preload_links.join("\n"))?;
+        }
         let file = std::fs::File::create(file_path)?;
         let mut file = std::io::BufWriter::new(file);
         file.write_all(&data)?;
@@ -65,7 +76,9 @@ impl FileSystemCache {
                 for entry in walkdir::WalkDir::new(entry.path()).into_iter().flatten() {
                     if entry.file_type().is_file() {
                         if let Some(fnmae) = entry.file_name().to_str() {
-                            if fnmae.ends_with(".html") {
+                            if fnmae.ends_with(".html")
+                                || fnmae.ends_with(&format!(".{LINKS_EXTENSION}"))
+                            {
                                 if let Err(err) = std::fs::remove_file(entry.path()) {
                                     tracing::error!("Failed to remove file: {}", err);
                                 }
@@ -79,23 +92,29 @@ impl FileSystemCache {
 
     pub fn invalidate(&mut self, route: &str) {
         let file_path = self.find_file(route).unwrap().full_path;
+        _ = std::fs::remove_file(file_path.with_extension(LINKS_EXTENSION));
         if let Err(err) = std::fs::remove_file(file_path) {
             tracing::error!("Failed to remove file: {}", err);
         }
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn get(
         &self,
         route: &str,
-    ) -> Result<Option<(RenderFreshness, Vec<u8>)>, IncrementalRendererError> {
+    ) -> Result<Option<(RenderFreshness, Vec<u8>, Vec<String>)>, IncrementalRendererError> {
         if let Some(file_path) = self.find_file(route) {
             if let Some(freshness) = file_path.freshness(self.invalidate_after) {
+                let preload_links =
+                    std::fs::read_to_string(file_path.full_path.with_extension(LINKS_EXTENSION))
+                        .map(|links| links.lines().map(String::from).collect())
+                        .unwrap_or_default();
                 if let Ok(file) = std::fs::File::open(file_path.full_path) {
                     let mut file = std::io::BufReader::new(file);
                     let mut cache_hit = Vec::new();
                     std::io::copy(&mut file, &mut cache_hit)?;
                     tracing::trace!("file cache hit {:?}", route);
-                    return Ok(Some((freshness, cache_hit)));
+                    return Ok(Some((freshness, cache_hit, preload_links)));
                 }
             }
         }
@@ -118,6 +137,7 @@ impl FileSystemCache {
                             }
                         }
                         // if the timestamp is invalid or passed, delete the file
+                        _ = std::fs::remove_file(entry.path().with_extension(LINKS_EXTENSION));
                         if let Err(err) = std::fs::remove_file(entry.path()) {
                             tracing::error!("Failed to remove file: {}", err);
                         }
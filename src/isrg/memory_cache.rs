@@ -9,9 +9,11 @@ use std::{hash::BuildHasherDefault, num::NonZeroUsize};
 
 use super::freshness::RenderFreshness;
 
+/// A cached render with the time it was created and the `Link` preload header values collected from its head
+pub(crate) type CacheEntry = (DateTime<Utc>, Vec<u8>, Vec<String>);
+
 pub(crate) struct InMemoryCache {
-    #[allow(clippy::type_complexity)]
-    lru: Option<lru::LruCache<String, (DateTime<Utc>, Vec<u8>), BuildHasherDefault<FxHasher>>>,
+    lru: Option<lru::LruCache<String, CacheEntry, BuildHasherDefault<FxHasher>>>,
     invalidate_after: Option<std::time::Duration>,
 }
 
@@ -30,9 +32,15 @@ impl InMemoryCache {
         }
     }
 
-    pub fn put(&mut self, route: String, timestamp: DateTime<Utc>, data: Vec<u8>) {
+    pub fn put(
+        &mut self,
+        route: String,
+        timestamp: DateTime<Utc>,
+        data: Vec<u8>,
+        preload_links: Vec<String>,
+    ) {
         if let Some(cache) = &mut self.lru {
-            cache.put(route, (timestamp, data));
+            cache.put(route, (timestamp, data, preload_links));
         }
     }
 
@@ -42,13 +50,14 @@ impl InMemoryCache {
         }
     }
 
-    pub fn try_get_or_insert<'a, F: FnOnce() -> Result<(DateTime<Utc>, Vec<u8>), E>, E>(
+    #[allow(clippy::type_complexity)]
+    pub fn try_get_or_insert<'a, F: FnOnce() -> Result<CacheEntry, E>, E>(
         &'a mut self,
         route: &str,
         or_insert: F,
-    ) -> Result<Option<(RenderFreshness, &'a [u8])>, E> {
+    ) -> Result<Option<(RenderFreshness, &'a [u8], &'a [String])>, E> {
         if let Some(memory_cache) = self.lru.as_mut() {
-            let (timestamp, _) = memory_cache.try_get_or_insert(route.to_string(), or_insert)?;
+            let (timestamp, ..) = memory_cache.try_get_or_insert(route.to_string(), or_insert)?;
 
             let now = Utc::now();
             let elapsed = timestamp.signed_duration_since(now);
@@ -67,19 +76,19 @@ impl InMemoryCache {
 
             // We need to reborrow because we may have invalidated the lifetime if the route was removed.
             // We know it wasn't because we returned... but rust doesn't understand that.
-            let (timestamp, cache_hit) = memory_cache.get(route).unwrap();
+            let (timestamp, cache_hit, preload_links) = memory_cache.get(route).unwrap();
 
             return match self.invalidate_after {
                 Some(invalidate_after) => {
                     tracing::trace!("memory cache hit");
                     let max_age = invalidate_after.as_secs();
                     let freshness = RenderFreshness::new(age as u64, max_age, *timestamp);
-                    Ok(Some((freshness, cache_hit)))
+                    Ok(Some((freshness, cache_hit, preload_links)))
                 }
                 None => {
                     tracing::trace!("memory cache hit");
                     let freshness = RenderFreshness::new_age(age as u64, *timestamp);
-                    Ok(Some((freshness, cache_hit)))
+                    Ok(Some((freshness, cache_hit, preload_links)))
                 }
             };
         }
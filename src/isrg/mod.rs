@@ -24,6 +24,8 @@ pub struct CachedRender<'a> {
     pub freshness: RenderFreshness,
     /// The rendered response
     pub response: &'a [u8],
+    /// The `Link` preload header values collected from the head of the render
+    pub preload_links: &'a [String],
 }
 
 /// An incremental renderer.
@@ -67,13 +69,24 @@ impl IncrementalRenderer {
         &mut self,
         route: String,
         html: impl Into<Vec<u8>>,
+    ) -> Result<RenderFreshness, IncrementalRendererError> {
+        self.cache_with_preload_links(route, html, Vec::new())
+    }
+
+    /// Cache a rendered response along with the `Link` preload header values collected from its head, so cache hits
+    /// send the same preload headers as the render.
+    pub(crate) fn cache_with_preload_links(
+        &mut self,
+        route: String,
+        html: impl Into<Vec<u8>>,
+        preload_links: Vec<String>,
     ) -> Result<RenderFreshness, IncrementalRendererError> {
         let timestamp = Utc::now();
         let html = html.into();
         #[cfg(not(target_arch = "wasm32"))]
         self.file_system_cache
-            .put(route.clone(), timestamp, html.clone())?;
-        self.memory_cache.put(route, timestamp, html);
+            .put(route.clone(), timestamp, html.clone(), &preload_links)?;
+        self.memory_cache.put(route, timestamp, html, preload_links);
         Ok(RenderFreshness::created_at(
             timestamp,
             self.invalidate_after,
@@ -125,7 +138,9 @@ impl IncrementalRenderer {
             // check the file cache
             #[cfg(not(target_arch = "wasm32"))]
             return match file_system_cache.get(route) {
-                Ok(Some((freshness, bytes))) => Ok((freshness.timestamp(), bytes)),
+                Ok(Some((freshness, bytes, preload_links))) => {
+                    Ok((freshness.timestamp(), bytes, preload_links))
+                }
                 Ok(None) => Err(FsGetError::NotPresent),
                 Err(e) => Err(FsGetError::Error(e)),
             };
@@ -135,10 +150,11 @@ impl IncrementalRenderer {
         };
 
         match memory_cache.try_get_or_insert(route, or_insert) {
-            Ok(Some((freshness, bytes))) => Ok(Some(CachedRender {
+            Ok(Some((freshness, bytes, preload_links))) => Ok(Some(CachedRender {
                 route: route.to_string(),
                 freshness,
                 response: bytes,
+                preload_links,
             })),
             Err(FsGetError::NotPresent) | Ok(None) => Ok(None),
             Err(FsGetError::Error(e)) => Err(e),
@@ -162,3 +178,67 @@ pub enum IncrementalRendererError {
     #[error("Unknown error: {0}")]
     Other(#[from] dioxus_core::CapturedError),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn static_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("dioxus-isrg-{name}-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn links() -> Vec<String> {
+        vec![
+            "</assets/app.js>; rel=modulepreload".to_string(),
+            "<https://fonts.example.com>; rel=preconnect".to_string(),
+        ]
+    }
+
+    #[test]
+    fn cached_renders_keep_their_preload_links() {
+        let dir = static_dir("memory");
+        let mut renderer = IncrementalRenderer::builder().static_dir(&dir).build();
+        renderer
+            .cache_with_preload_links("/posts".to_string(), "<html></html>", links())
+            .unwrap();
+
+        let cached = renderer.get("/posts").unwrap().unwrap();
+        assert_eq!(cached.response, b"<html></html>");
+        assert_eq!(cached.preload_links, links());
+        _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn preload_links_are_read_back_from_the_file_system() {
+        let dir = static_dir("fs");
+        // The file system cache is only read when cached routes expire
+        let builder = || {
+            IncrementalRenderer::builder()
+                .static_dir(&dir)
+                .invalidate_after(Duration::from_secs(60))
+        };
+        let mut renderer = builder().build();
+        renderer
+            .cache_with_preload_links("/posts".to_string(), "<html></html>", links())
+            .unwrap();
+        renderer
+            .cache("/about".to_string(), "<html>about</html>")
+            .unwrap();
+
+        // A new renderer only has the file system cache, like after a restart
+        let mut renderer = builder().build();
+        let cached = renderer.get("/posts").unwrap().unwrap();
+        assert_eq!(cached.preload_links, links());
+        let cached = renderer.get("/about").unwrap().unwrap();
+        assert!(cached.preload_links.is_empty());
+
+        renderer.invalidate("/posts");
+        let remaining = std::fs::read_dir(dir.join("posts").join("index"))
+            .unwrap()
+            .count();
+        assert_eq!(remaining, 0);
+        _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,6 +1,6 @@
 //! A launch function that creates an axum router for the LaunchBuilder
 
-use crate::{server::DioxusRouterExt, FullstackState, ServeConfig};
+use crate::{early_hints::EarlyHintsIo, server::DioxusRouterExt, FullstackState, ServeConfig};
 use anyhow::Context;
 use axum::{
     body::Body,
@@ -28,7 +28,7 @@ use tower::{Service, ServiceExt as _};
 #[cfg(not(target_arch = "wasm32"))]
 use {
     dioxus_core::{RenderError, VNode},
-    tokio::net::TcpListener,
+    tokio::net::{TcpListener, TcpStream},
 };
 
 type ContextList = Vec<Box<dyn Fn() -> Box<dyn Any> + Send + Sync>>;
@@ -144,10 +144,19 @@ pub async fn serve_router(
 
     // If we're not in debug mode, just serve the app normally
     if !cfg!(debug_assertions) {
-        axum::serve(listener, serve_callback().await.unwrap())
-            .await
-            .unwrap();
-        return;
+        let make_service = serve_callback().await.unwrap().into_make_service();
+        loop {
+            match listener.accept().await {
+                Ok((tcp_stream, _remote_addr)) => {
+                    tokio::task::spawn(serve_connection(tcp_stream, make_service.clone()));
+                }
+                Err(err) => {
+                    // Errors like running out of file descriptors go away once other connections close
+                    tracing::error!("Failed to accept connection: {err}");
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
     }
 
     // Wire up the devtools connection. The sender only sends messages in dev.
@@ -174,36 +183,12 @@ pub async fn serve_router(
 
         match res {
             Either::Left(Ok((tcp_stream, _remote_addr))) => {
-                let mut make_service = make_service.clone();
+                let make_service = make_service.clone();
                 let mut shutdown_rx = shutdown_tx.subscribe();
 
                 tokio::task::spawn(async move {
-                    let tcp_stream = TokioIo::new(tcp_stream);
-
-                    std::future::poll_fn(|cx| {
-                        <IntoMakeService<Router> as tower::Service<Request>>::poll_ready(
-                            &mut make_service,
-                            cx,
-                        )
-                    })
-                    .await
-                    .expect("Infallible");
-
-                    // upgrades needed for websockets
-                    let builder = HyperBuilder::new(TokioExecutor::new());
-                    let connection = builder.serve_connection_with_upgrades(
-                        tcp_stream,
-                        TowerToHyperService::new(
-                            make_service
-                                .call(())
-                                .await
-                                .unwrap()
-                                .map_request(|req: Request<Incoming>| req.map(Body::new)),
-                        ),
-                    );
-
                     tokio::select! {
-                        res = connection => {
+                        res = serve_connection(tcp_stream, make_service) => {
                             if let Err(_err) = res {
                                 // This error only appears when the client doesn't send a request and
                                 // terminate the connection.
@@ -250,6 +235,36 @@ pub async fn serve_router(
     }
 }
 
+/// Serve the requests on a connection. The connection is wrapped so the render handler can send `103 Early Hints`
+/// on it before the page is rendered.
+pub(crate) async fn serve_connection(
+    tcp_stream: TcpStream,
+    mut make_service: IntoMakeService<Router>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (tcp_stream, early_hints) = EarlyHintsIo::new(tcp_stream);
+
+    std::future::poll_fn(|cx| {
+        <IntoMakeService<Router> as tower::Service<Request>>::poll_ready(&mut make_service, cx)
+    })
+    .await
+    .expect("Infallible");
+
+    // upgrades needed for websockets
+    let builder = HyperBuilder::new(TokioExecutor::new());
+    builder
+        .serve_connection_with_upgrades(
+            TokioIo::new(tcp_stream),
+            TowerToHyperService::new(make_service.call(()).await.unwrap().map_request(
+                move |req: Request<Incoming>| {
+                    let mut req = req.map(Body::new);
+                    req.extensions_mut().insert(early_hints.clone());
+                    req
+                },
+            )),
+        )
+        .await
+}
+
 fn block_on<T>(app_future: impl Future<Output = T>) {
     if let Ok(handle) = tokio::runtime::Handle::try_current() {
         handle.block_on(app_future);
@@ -15,17 +15,20 @@ pub use axum;
 pub use http;
 pub use inventory;
 
-// --- Server-only modules and re-exports ---
-#[cfg(not(target_arch = "wasm32"))]
+// --- Modules and re-exports for server side rendering. These are available on wasm32 as well ---
 pub use config::ServeConfig;
-#[cfg(not(target_arch = "wasm32"))]
 pub use config::*;
-#[cfg(not(target_arch = "wasm32"))]
-pub use document::ServerDocument;
+pub use document::{CspNonce, ServerDocument};
+pub use index_html::IndexHtml;
+pub use render_mode::use_fragment_boundary;
 
+// --- Server-only modules and re-exports ---
 #[cfg(not(target_arch = "wasm32"))]
 pub mod redirect;
 
+#[cfg(not(target_arch = "wasm32"))]
+mod early_hints;
+
 #[cfg(not(target_arch = "wasm32"))]
 mod launch;
 
@@ -41,25 +44,26 @@ pub use launch::serve;
 pub mod server;
 pub use server::*;
 
-#[cfg(not(target_arch = "wasm32"))]
 pub mod config;
 
-#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod compression;
 pub(crate) mod document;
-#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod render_mode;
 pub(crate) mod ssr;
-#[cfg(not(target_arch = "wasm32"))]
 pub(crate) mod streaming;
 
 pub mod serverfn;
 pub use serverfn::*;
 
-#[cfg(not(target_arch = "wasm32"))]
 pub mod isrg;
-#[cfg(not(target_arch = "wasm32"))]
 pub use isrg::*;
 
-#[cfg(not(target_arch = "wasm32"))]
 mod index_html;
+
+mod worker;
+pub use worker::{worker_bindings, WorkerBindings};
+
+mod defer;
 #[cfg(not(target_arch = "wasm32"))]
-pub(crate) use index_html::IndexHtml;
+pub use defer::DeferredWork;
+pub use defer::{defer, DeferSink, DeferredFuture};
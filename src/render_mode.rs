@@ -0,0 +1,210 @@
+//! Render modes for requests that only need part of a page.
+use crate::ssr::SSRError;
+use dioxus_core::{provide_context, use_hook};
+use http::{request::Parts, StatusCode, Uri};
+
+/// The header that selects a fragment render. The value is the name of the fragment boundary to render, or empty to
+/// render the root of the app.
+pub(crate) const FRAGMENT_HEADER: &str = "dx-fragment";
+
+/// The query parameter that selects a fragment render. It is removed from the route before the app is rendered.
+pub(crate) const FRAGMENT_QUERY: &str = "dx-fragment";
+
+/// The header that selects a route data render
+pub(crate) const ROUTE_DATA_HEADER: &str = "dx-route-data";
+
+/// The query flag that selects a route data render. It is removed from the route before the app is rendered.
+pub(crate) const ROUTE_DATA_QUERY: &str = "dx-route-data";
+
+/// How the server should render a request
+#[derive(Clone, Debug, PartialEq, Default)]
+pub(crate) enum RenderMode {
+    /// Render the full document inside the `index.html` shell
+    #[default]
+    Document,
+
+    /// Render only the html and hydration data for part of the app without the `index.html` shell
+    Fragment(FragmentTarget),
+
+    /// Render only the hydration data for the whole route as json once all suspense has resolved. The client can
+    /// use this to prefetch the data for a route in one request instead of calling each server function.
+    Data,
+}
+
+/// The part of the app a fragment render sends to the client
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum FragmentTarget {
+    /// The root of the app
+    Root,
+
+    /// The first component in the app that called [`use_fragment_boundary`] with this name
+    Boundary(String),
+}
+
+impl RenderMode {
+    /// The request headers the render mode depends on. Responses that may be rendered in a different mode must
+    /// include these in the `Vary` header so caches don't mix them up.
+    pub(crate) const VARY: &'static str = "dx-fragment, dx-route-data";
+
+    /// Read the render mode from the request. If the mode was selected with a query flag, the flag is removed from the
+    /// uri so the router sees the original route.
+    ///
+    /// Returns a `400 Bad Request` error if the fragment boundary name is not valid.
+    pub(crate) fn from_request(parts: &mut Parts) -> Result<Self, SSRError> {
+        if let Some(value) = parts.headers.get(FRAGMENT_HEADER) {
+            let name = value.to_str().unwrap_or_default().trim();
+            return FragmentTarget::from_name(name).map(Self::Fragment);
+        }
+
+        if parts.headers.contains_key(ROUTE_DATA_HEADER) {
+            return Ok(Self::Data);
+        }
+
+        if let Some(name) = take_query_flag(&mut parts.uri, FRAGMENT_QUERY) {
+            return FragmentTarget::from_name(&name).map(Self::Fragment);
+        }
+
+        if take_query_flag(&mut parts.uri, ROUTE_DATA_QUERY).is_some() {
+            return Ok(Self::Data);
+        }
+
+        Ok(Self::Document)
+    }
+
+    /// The key the render is stored under in the incremental cache. Partial renders are stored separately from the
+    /// full document of the route.
+    pub(crate) fn cache_key(&self, route: &str) -> String {
+        match self {
+            Self::Document => route.to_string(),
+            Self::Fragment(FragmentTarget::Root) => format!("/__dx_fragment/root{route}"),
+            Self::Fragment(FragmentTarget::Boundary(name)) => {
+                format!("/__dx_fragment/boundary/{name}{route}")
+            }
+            Self::Data => format!("/__dx_data{route}"),
+        }
+    }
+
+    /// The content type of the response body
+    pub(crate) fn content_type(&self) -> &'static str {
+        match self {
+            Self::Document | Self::Fragment(_) => "text/html; charset=utf-8",
+            Self::Data => "application/json",
+        }
+    }
+}
+
+impl FragmentTarget {
+    /// Read the target from the value of the header or query flag. Boundary names are part of the incremental cache
+    /// key, which the file system cache maps to a path, so they may only contain ascii letters, digits, `_` and `-`.
+    fn from_name(name: &str) -> Result<Self, SSRError> {
+        match name {
+            "" | "root" => Ok(Self::Root),
+            name if name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-')) =>
+            {
+                Ok(Self::Boundary(name.to_string()))
+            }
+            _ => Err(SSRError::HttpError {
+                status: StatusCode::BAD_REQUEST,
+                message: Some(
+                    "Fragment boundary names may only contain ascii letters, digits, `_` and `-`"
+                        .to_string(),
+                ),
+            }),
+        }
+    }
+}
+
+/// The name of a fragment boundary provided by [`use_fragment_boundary`]
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FragmentBoundary(pub(crate) String);
+
+/// Mark the current component as a fragment boundary. A request with the `dx-fragment: <name>` header or the
+/// `?dx-fragment=<name>` query flag renders only this component and its hydration data instead of the whole page.
+/// Javascript evaluated with `eval` during the render is sent in a script at the end of the fragment, so the client
+/// should run the scripts in the fragment when it inserts it.
+///
+/// Names may only contain ascii letters, digits, `_` and `-`. Requests for other names get a `400 Bad Request`.
+pub fn use_fragment_boundary(name: impl Into<String>) {
+    use_hook(|| provide_context(FragmentBoundary(name.into())));
+}
+
+/// Remove a query parameter from the uri and return its percent-decoded value if it was present. The other parameters
+/// are kept as they were sent.
+fn take_query_flag(uri: &mut Uri, flag: &str) -> Option<String> {
+    let path_and_query = uri.path_and_query()?;
+    let query = path_and_query.query()?;
+
+    let mut value = None;
+    let remaining = query
+        .split('&')
+        .filter(|pair| {
+            if value.is_some() {
+                return true;
+            }
+            match form_urlencoded::parse(pair.as_bytes()).next() {
+                Some((key, pair_value)) if key == flag => {
+                    value = Some(pair_value.into_owned());
+                    false
+                }
+                _ => true,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    let value = value?;
+
+    let path = path_and_query.path();
+    let path_and_query = if remaining.is_empty() {
+        path.to_string()
+    } else {
+        format!("{path}?{remaining}")
+    };
+    let mut uri_parts = std::mem::take(uri).into_parts();
+    uri_parts.path_and_query = path_and_query.parse().ok();
+    *uri = Uri::from_parts(uri_parts).unwrap_or_default();
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(uri: &str, flag: &str) -> (Option<String>, String) {
+        let mut uri: Uri = uri.parse().unwrap();
+        let value = take_query_flag(&mut uri, flag);
+        (value, uri.to_string())
+    }
+
+    #[test]
+    fn query_flags_are_percent_decoded() {
+        assert_eq!(
+            take("/posts?dx-fragment=post%20list&page=2", FRAGMENT_QUERY),
+            (Some("post list".to_string()), "/posts?page=2".to_string())
+        );
+        assert_eq!(
+            take("/posts?dx%2Dfragment=comments+list", FRAGMENT_QUERY),
+            (Some("comments list".to_string()), "/posts".to_string())
+        );
+    }
+
+    #[test]
+    fn other_query_parameters_are_kept_as_they_were_sent() {
+        assert_eq!(
+            take(
+                "/search?q=a%26b&dx-route-data&sort=new+first",
+                ROUTE_DATA_QUERY
+            ),
+            (
+                Some(String::new()),
+                "/search?q=a%26b&sort=new+first".to_string()
+            )
+        );
+        assert_eq!(
+            take("/search?q=dx-route-data", ROUTE_DATA_QUERY),
+            (None, "/search?q=dx-route-data".to_string())
+        );
+    }
+}
@@ -1,24 +1,356 @@
 // ============================================================================
-// Native (non-WASM) — full FullstackState with SSR, tokio LocalPool, etc.
+// Shared — FullstackState and server side rendering. On native, renders run on
+// a tokio LocalPool. On WASM, they run on the single threaded executor.
 // ============================================================================
+use crate::{
+    config::{ContextProvider, RequestContextProvider},
+    render_mode::RenderMode,
+    ssr::{RenderRuntime, SSRError, SsrRendererPool},
+    CspNonce, ErrorPageProps, ServeConfig, ServerFunction,
+};
+use axum::{
+    body::Body,
+    extract::{OriginalUri, State},
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use dioxus_core::{ComponentFunction, VirtualDom};
+use dioxus_fullstack_core::{FullstackContext, ServerFnError};
+use http::{header::*, request::Parts};
+use std::sync::Arc;
+
+pub async fn render_handler(
+    State(state): State<FullstackState>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    FullstackState::render_handler(State(state), request).await
+}
+
+/// State used by [`FullstackState::render_handler`] to render a dioxus component with axum
+#[derive(Clone)]
+pub struct FullstackState {
+    pub(crate) config: ServeConfig,
+    pub(crate) context_providers: ServerContextProviders,
+    build_virtual_dom: Arc<dyn Fn() -> VirtualDom + Send + Sync>,
+    renderers: Arc<SsrRendererPool>,
+    pub(crate) rt: RenderRuntime,
+}
+
+/// The context providers from the [`ServeConfig`]. They are added to the request extensions of every render and
+/// server function, so the contexts can be read from the [`FullstackContext`] with [`server_context`].
+#[derive(Clone, Default)]
+pub struct ServerContextProviders {
+    providers: Arc<Vec<ContextProvider>>,
+    request_providers: Arc<Vec<RequestContextProvider>>,
+}
+
+impl ServerContextProviders {
+    fn new(config: &ServeConfig) -> Self {
+        Self {
+            providers: Arc::new(config.context_providers.clone()),
+            request_providers: Arc::new(config.request_context_providers.clone()),
+        }
+    }
+
+    /// Run the per-request providers for a request. The returned providers include the contexts for the request
+    /// after the contexts from the config.
+    pub(crate) async fn for_request(&self, parts: &Parts) -> Self {
+        if self.request_providers.is_empty() {
+            return self.clone();
+        }
+
+        let mut providers = self.providers.as_ref().clone();
+        for provider in self.request_providers.iter() {
+            providers.push(provider(parts).await);
+        }
+        Self {
+            providers: Arc::new(providers),
+            request_providers: Default::default(),
+        }
+    }
+
+    /// Create a context of type `T` with the last provider that provides it
+    pub fn get<T: 'static>(&self) -> Option<T> {
+        self.providers
+            .iter()
+            .rev()
+            .find_map(|provider| provider().downcast::<T>().ok())
+            .map(|context| *context)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &ContextProvider> {
+        self.providers.iter()
+    }
+}
+
+/// Get a context provided with [`ServeConfig::context`], [`ServeConfig::context_provider`],
+/// [`ServeConfig::context_providers`] or [`ServeConfig::request_context_provider`] from the current request. This works inside server functions on every target
+/// and while rendering the app.
+///
+/// Returns `None` outside of a request or if no provider creates a context of type `T`.
+///
+/// ```rust, no_run
+/// # #[derive(Clone)]
+/// # struct DbPool;
+/// async fn list_users() -> Result<(), &'static str> {
+///     let pool: DbPool = dioxus_server::server_context().ok_or("the database pool is not configured")?;
+///     Ok(())
+/// }
+/// ```
+pub fn server_context<T: 'static>() -> Option<T> {
+    FullstackContext::current()?
+        .extension::<ServerContextProviders>()?
+        .get()
+}
+
+impl FullstackState {
+    pub fn headless() -> Self {
+        let rt = render_runtime();
+        Self {
+            renderers: Arc::new(SsrRendererPool::new(4, None)),
+            build_virtual_dom: Arc::new(|| {
+                panic!("No root component provided for headless FullstackState")
+            }),
+            context_providers: Default::default(),
+            config: ServeConfig::new(),
+            rt,
+        }
+    }
+
+    pub fn new<M: 'static>(
+        config: ServeConfig,
+        root: impl ComponentFunction<(), M> + Send + Sync + 'static,
+    ) -> Self {
+        let rt = render_runtime();
+        Self {
+            renderers: Arc::new(SsrRendererPool::new(4, config.incremental.clone())),
+            build_virtual_dom: Arc::new(move || VirtualDom::new_with_props(root.clone(), ())),
+            context_providers: ServerContextProviders::new(&config),
+            config,
+            rt,
+        }
+    }
+
+    pub fn new_with_virtual_dom_factory(
+        config: ServeConfig,
+        build_virtual_dom: impl Fn() -> VirtualDom + Send + Sync + 'static,
+    ) -> Self {
+        let rt = render_runtime();
+        Self {
+            renderers: Arc::new(SsrRendererPool::new(4, config.incremental.clone())),
+            context_providers: ServerContextProviders::new(&config),
+            config,
+            build_virtual_dom: Arc::new(build_virtual_dom),
+            rt,
+        }
+    }
+
+    pub fn with_config(mut self, config: ServeConfig) -> Self {
+        self.context_providers = ServerContextProviders::new(&config);
+        self.config = config;
+        self
+    }
+
+    pub async fn render_handler(State(state): State<Self>, request: Request<Body>) -> Response {
+        let (mut parts, _) = request.into_parts();
+        let mode = match RenderMode::from_request(&mut parts) {
+            Ok(mode) => mode,
+            Err(err) => return state.error_response(err.into_error_page_props()),
+        };
+        let content_type = mode.content_type();
+        let is_route_data = mode == RenderMode::Data;
+        #[cfg(not(target_arch = "wasm32"))]
+        if mode == RenderMode::Document {
+            state.send_early_hints(&parts).await;
+        }
+        let response = state
+            .renderers
+            .clone()
+            .render_to(
+                parts,
+                mode,
+                &state.config,
+                &state.rt,
+                state.context_providers.clone(),
+                {
+                    let build_virtual_dom = state.build_virtual_dom.clone();
+                    move || build_virtual_dom()
+                },
+            )
+            .await;
+
+        match response {
+            Ok((status, headers, freshness, rx)) => {
+                let mut response = Response::builder()
+                    .status(status.status)
+                    .header(CONTENT_TYPE, content_type)
+                    .header(VARY, RenderMode::VARY)
+                    .body(Body::from_stream(rx))
+                    .unwrap();
+                freshness.write(response.headers_mut());
+                // Extending keeps every value of headers like `Link` that the app and the config both add to
+                response.headers_mut().extend(headers);
+                response
+            }
+            Err(err) => {
+                if let SSRError::Incremental(e) = &err {
+                    tracing::error!("Failed to render page: {}", e);
+                }
+                let props = err.into_error_page_props();
+                if is_route_data {
+                    data_error_response(props)
+                } else {
+                    state.error_response(props)
+                }
+            }
+        }
+    }
+
+    /// Send a `103 Early Hints` response with the bundle from the `index.html` if early hints are enabled and the
+    /// connection supports them
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn send_early_hints(&self, parts: &Parts) {
+        let links = &self.config.index.preload_links;
+        if !self.config.early_hints || links.is_empty() || parts.version != http::Version::HTTP_11 {
+            return;
+        }
+        if let Some(early_hints) = parts.extensions.get::<crate::early_hints::EarlyHints>() {
+            if let Err(err) = early_hints.send(links).await {
+                tracing::debug!("Failed to send early hints: {err}");
+            }
+        }
+    }
+
+    /// Create the response for a request that failed before the first chunk was rendered. This uses the
+    /// error page from the [`ServeConfig`] if one is registered for the status.
+    fn error_response(&self, props: ErrorPageProps) -> Response {
+        let status = props.status;
+        // The error page is a new document, so it gets a new nonce and the same `Content-Security-Policy` as pages
+        let nonce = self.config.csp_nonce.then(CspNonce::generate);
+        let nonce = nonce.as_ref().map(|nonce| nonce.0.as_str());
+        let mut headers = HeaderMap::new();
+        self.config
+            .write_content_security_policy(&mut headers, nonce);
+
+        let mut response =
+            match SsrRendererPool::render_error_page(&self.config, props.clone(), nonce) {
+                Some(Ok(html)) => Response::builder()
+                    .status(status)
+                    .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                    .body(Body::from(html))
+                    .unwrap(),
+                result => {
+                    if let Some(Err(e)) = result {
+                        tracing::error!("Failed to render error page: {}", e);
+                    }
+                    Response::builder()
+                        .status(status)
+                        .body(Body::from(props.fallback_message()))
+                        .unwrap()
+                }
+            };
+        response.headers_mut().extend(headers);
+        response
+    }
+}
+
+/// Create the runtime renders are spawned on. On native, this is a pool with one single threaded runtime per core
 #[cfg(not(target_arch = "wasm32"))]
-mod native {
-    use crate::{
-        ssr::{SSRError, SsrRendererPool},
-        ServeConfig, ServerFunction,
-    };
-    use axum::{
-        body::Body,
-        extract::State,
-        http::{Request, StatusCode},
-        response::{IntoResponse, Response},
-        routing::*,
+fn render_runtime() -> RenderRuntime {
+    RenderRuntime::new(
+        std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(1),
+    )
+}
+
+/// Create the runtime renders are spawned on. On wasm32, renders run on the executor of the request
+#[cfg(target_arch = "wasm32")]
+fn render_runtime() -> RenderRuntime {
+    RenderRuntime
+}
+
+/// Create a router with every registered server function and a headless [`FullstackState`]. This works on every
+/// target, so Workers apps can serve server functions without rendering the app.
+///
+/// If a prefix is given, the server functions are mounted under it, and requests under the prefix that don't match a
+/// server function get a 404 with a JSON [`ServerFnError`] body. The router has no fallback of its own, so it can be
+/// merged into an app router that has one.
+///
+/// ```rust, no_run
+/// let router = dioxus_server::server_functions_router(Some("/functions"));
+/// ```
+pub fn server_functions_router(prefix: Option<&str>) -> Router<()> {
+    server_functions_router_with_config(prefix, ServeConfig::new())
+}
+
+/// Create a router with every registered server function like [`server_functions_router`], using the options from
+/// the [`ServeConfig`] that apply to server functions, like
+/// [`ServeConfig::referrer_redirect`](crate::ServeConfig::referrer_redirect).
+pub fn server_functions_router_with_config(prefix: Option<&str>, cfg: ServeConfig) -> Router<()> {
+    let state = FullstackState::headless().with_config(cfg);
+    let router = register_server_functions(Router::new());
+    match prefix.map(|prefix| prefix.trim_end_matches('/')) {
+        Some(prefix) if !prefix.is_empty() => Router::new()
+            .nest(prefix, router.fallback(server_function_not_found))
+            .with_state(state),
+        _ => router.with_state(state),
+    }
+}
+
+/// Add a route for every registered server function. If several server functions share a method and path, only the
+/// first is registered
+pub(crate) fn register_server_functions(
+    mut router: Router<FullstackState>,
+) -> Router<FullstackState> {
+    use std::collections::HashSet;
+    let mut seen = HashSet::new();
+    for func in ServerFunction::collect() {
+        if seen.insert(format!("{} {}", func.method(), func.path())) {
+            tracing::info!("Registering: {} {}", func.method(), func.path());
+            router = router.route(func.path(), func.method_router())
+        }
+    }
+    router
+}
+
+/// Create the response for a route data request that failed. The client asked for json, so the error is sent in the
+/// same format as server function errors instead of as an error page.
+fn data_error_response(props: ErrorPageProps) -> Response {
+    let status = props.status;
+    let error = ServerFnError::ServerError {
+        message: props.fallback_message(),
+        code: status.as_u16(),
+        details: props.details.map(serde_json::Value::String),
     };
-    use dioxus_core::{ComponentFunction, VirtualDom};
+    (status, Json(error)).into_response()
+}
+
+async fn server_function_not_found(
+    OriginalUri(uri): OriginalUri,
+) -> (StatusCode, Json<ServerFnError>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ServerFnError::ServerError {
+            message: format!("No server function found at {}", uri.path()),
+            code: StatusCode::NOT_FOUND.as_u16(),
+            details: None,
+        }),
+    )
+}
+
+// ============================================================================
+// Native (non-WASM) — router integration and static assets from the filesystem.
+// ============================================================================
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use super::{register_server_functions, FullstackState};
+    use crate::ServeConfig;
+    use axum::{body::Body, http::Request, response::Response, routing::*};
+    use dioxus_core::ComponentFunction;
     use http::header::*;
     use std::path::{Path, PathBuf};
-    use std::sync::Arc;
-    use tokio_util::task::LocalPoolHandle;
     use tower::util::MapResponse;
     use tower::ServiceExt;
     use tower_http::services::fs::ServeFileSystemResponseBody;
@@ -43,16 +375,8 @@ mod native {
     }
 
     impl DioxusRouterExt for Router<FullstackState> {
-        fn register_server_functions(mut self) -> Router<FullstackState> {
-            use std::collections::HashSet;
-            let mut seen = HashSet::new();
-            for func in ServerFunction::collect() {
-                if seen.insert(format!("{} {}", func.method(), func.path())) {
-                    tracing::info!("Registering: {} {}", func.method(), func.path());
-                    self = self.route(func.path(), func.method_router())
-                }
-            }
-            self
+        fn register_server_functions(self) -> Router<FullstackState> {
+            register_server_functions(self)
         }
 
         fn serve_static_assets(self) -> Router<FullstackState> {
@@ -84,135 +408,6 @@ mod native {
         }
     }
 
-    pub async fn render_handler(
-        State(state): State<FullstackState>,
-        request: Request<Body>,
-    ) -> impl IntoResponse {
-        FullstackState::render_handler(State(state), request).await
-    }
-
-    /// State used by [`FullstackState::render_handler`] to render a dioxus component with axum
-    #[derive(Clone)]
-    pub struct FullstackState {
-        config: ServeConfig,
-        build_virtual_dom: Arc<dyn Fn() -> VirtualDom + Send + Sync>,
-        renderers: Arc<SsrRendererPool>,
-        pub(crate) rt: LocalPoolHandle,
-    }
-
-    impl FullstackState {
-        pub fn headless() -> Self {
-            let rt = LocalPoolHandle::new(
-                std::thread::available_parallelism()
-                    .map(usize::from)
-                    .unwrap_or(1),
-            );
-            Self {
-                renderers: Arc::new(SsrRendererPool::new(4, None)),
-                build_virtual_dom: Arc::new(|| {
-                    panic!("No root component provided for headless FullstackState")
-                }),
-                config: ServeConfig::new(),
-                rt,
-            }
-        }
-
-        pub fn new<M: 'static>(
-            config: ServeConfig,
-            root: impl ComponentFunction<(), M> + Send + Sync + 'static,
-        ) -> Self {
-            let rt = LocalPoolHandle::new(
-                std::thread::available_parallelism()
-                    .map(usize::from)
-                    .unwrap_or(1),
-            );
-            Self {
-                renderers: Arc::new(SsrRendererPool::new(4, config.incremental.clone())),
-                build_virtual_dom: Arc::new(move || VirtualDom::new_with_props(root.clone(), ())),
-                config,
-                rt,
-            }
-        }
-
-        pub fn new_with_virtual_dom_factory(
-            config: ServeConfig,
-            build_virtual_dom: impl Fn() -> VirtualDom + Send + Sync + 'static,
-        ) -> Self {
-            let rt = LocalPoolHandle::new(
-                std::thread::available_parallelism()
-                    .map(usize::from)
-                    .unwrap_or(1),
-            );
-            Self {
-                renderers: Arc::new(SsrRendererPool::new(4, config.incremental.clone())),
-                config,
-                build_virtual_dom: Arc::new(build_virtual_dom),
-                rt,
-            }
-        }
-
-        pub fn with_config(mut self, config: ServeConfig) -> Self {
-            self.config = config;
-            self
-        }
-
-        pub async fn render_handler(
-            State(state): State<Self>,
-            request: Request<Body>,
-        ) -> Response {
-            let (parts, _) = request.into_parts();
-            let response = state
-                .renderers
-                .clone()
-                .render_to(parts, &state.config, &state.rt, {
-                    let build_virtual_dom = state.build_virtual_dom.clone();
-                    let context_providers = state.config.context_providers.clone();
-                    move || {
-                        let mut vdom = build_virtual_dom();
-                        for state in context_providers.as_slice() {
-                            vdom.insert_any_root_context(state());
-                        }
-                        vdom
-                    }
-                })
-                .await;
-
-            match response {
-                Ok((status, headers, freshness, rx)) => {
-                    let mut response = Response::builder()
-                        .status(status.status)
-                        .header(CONTENT_TYPE, "text/html; charset=utf-8")
-                        .body(Body::from_stream(rx))
-                        .unwrap();
-                    freshness.write(response.headers_mut());
-                    for (key, value) in headers.into_iter() {
-                        if let Some(key) = key {
-                            response.headers_mut().insert(key, value);
-                        }
-                    }
-                    response
-                }
-                Err(SSRError::Incremental(e)) => {
-                    tracing::error!("Failed to render page: {}", e);
-                    Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(e.to_string())
-                        .unwrap()
-                        .into_response()
-                }
-                Err(SSRError::HttpError { status, message }) => Response::builder()
-                    .status(status)
-                    .body(Body::from(message.unwrap_or_else(|| {
-                        status
-                            .canonical_reason()
-                            .unwrap_or("An unknown error occurred")
-                            .to_string()
-                    })))
-                    .unwrap(),
-            }
-        }
-    }
-
     pub(crate) fn public_path() -> Option<PathBuf> {
         if let Ok(path) = std::env::var("DIOXUS_PUBLIC_PATH") {
             return Some(PathBuf::from(path));
@@ -226,20 +421,13 @@ mod native {
         )
     }
 
-    fn serve_dir_cached<S>(
-        mut router: Router<S>,
-        public_path: &Path,
-        directory: &Path,
-    ) -> Router<S>
+    fn serve_dir_cached<S>(mut router: Router<S>, public_path: &Path, directory: &Path) -> Router<S>
     where
         S: Send + Sync + Clone + 'static,
     {
         use tower_http::services::{ServeDir, ServeFile};
         let dir = std::fs::read_dir(directory).unwrap_or_else(|e| {
-            panic!(
-                "Couldn't read public directory at {:?}: {}",
-                &directory, e
-            )
+            panic!("Couldn't read public directory at {:?}: {}", &directory, e)
         });
         for entry in dir.flatten() {
             let path = entry.path();
@@ -306,25 +494,367 @@ mod native {
 #[cfg(not(target_arch = "wasm32"))]
 pub use native::*;
 
-// ============================================================================
-// WASM — minimal FullstackState stub for Cloudflare Workers / WASM targets.
-// No SSR, no tokio LocalPool, no filesystem.
-// ============================================================================
-#[cfg(target_arch = "wasm32")]
-mod wasm {
-    /// Minimal state stub for WASM targets. Server functions don't need SSR
-    /// or a tokio thread pool — they run directly in the single-threaded
-    /// WASM runtime.
-    #[derive(Clone)]
-    pub struct FullstackState;
-
-    impl FullstackState {
-        /// Create a headless state for WASM (no-op).
-        pub fn headless() -> Self {
-            Self
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorPage, IndexHtml, ServerFunction, StatusClass};
+    use axum::{body::Body, extract::Request, response::IntoResponse};
+    use dioxus_core::{use_hook, Element, VNode};
+    use http::header::{ACCEPT, LOCATION, REFERER};
+    use std::{future::Future, pin::Pin};
+    use tower::ServiceExt;
+
+    #[allow(clippy::type_complexity)]
+    fn ok(_: State<FullstackContext>, _: Request) -> Pin<Box<dyn Future<Output = Response>>> {
+        Box::pin(async { "ok".into_response() })
+    }
+
+    inventory::submit! {
+        ServerFunction::new(http::Method::GET, "/api/server_functions_router_ok", || {
+            ServerFunction::make_handler(http::Method::GET, ok)
+        })
+    }
+
+    async fn get(router: &Router, path: &str) -> (StatusCode, String) {
+        let request = http::Request::get(path).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn server_functions_are_mounted_under_the_prefix() {
+        let router = server_functions_router(Some("/functions/"));
+        assert_eq!(
+            get(&router, "/functions/api/server_functions_router_ok").await,
+            (StatusCode::OK, "ok".to_string())
+        );
+        assert_eq!(
+            get(&router, "/api/server_functions_router_ok").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn only_paths_under_the_prefix_get_the_server_function_error() {
+        let router = server_functions_router(Some("/functions"));
+
+        let (status, body) = get(&router, "/functions/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("No server function found at /functions/missing"));
+
+        assert_eq!(
+            get(&router, "/favicon.ico").await,
+            (StatusCode::NOT_FOUND, String::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn merges_into_an_app_router_with_a_fallback() {
+        for prefix in [Some("/functions"), None] {
+            let router = Router::new()
+                .fallback(|| async { "app" })
+                .merge(server_functions_router(prefix));
+            let path = format!(
+                "{}/api/server_functions_router_ok",
+                prefix.unwrap_or_default()
+            );
+
+            assert_eq!(
+                get(&router, &path).await,
+                (StatusCode::OK, "ok".to_string())
+            );
+            assert_eq!(
+                get(&router, "/about").await,
+                (StatusCode::OK, "app".to_string())
+            );
         }
+
+        let router = Router::new()
+            .fallback(|| async { "app" })
+            .merge(server_functions_router(Some("/functions")));
+        let (status, body) = get(&router, "/functions/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("No server function found"));
     }
-}
 
-#[cfg(target_arch = "wasm32")]
-pub use wasm::*;
+    #[allow(clippy::type_complexity)]
+    fn redirects(
+        _: State<FullstackContext>,
+        _: Request,
+    ) -> Pin<Box<dyn Future<Output = Response>>> {
+        Box::pin(async {
+            Response::builder()
+                .status(StatusCode::SEE_OTHER)
+                .header(LOCATION, "/done")
+                .body(Body::empty())
+                .unwrap()
+        })
+    }
+
+    inventory::submit! {
+        ServerFunction::new(http::Method::POST, "/api/referrer_redirect_ok", || {
+            ServerFunction::make_handler(http::Method::POST, ok)
+        })
+    }
+
+    inventory::submit! {
+        ServerFunction::new(http::Method::POST, "/api/referrer_redirect_location", || {
+            ServerFunction::make_handler(http::Method::POST, redirects)
+        })
+    }
+
+    /// Post to a server function like an html form on `/form` would, with the given `Accept` header
+    async fn submit_form(cfg: ServeConfig, path: &str, accept: &str) -> Response {
+        let request = http::Request::post(path)
+            .header(ACCEPT, accept)
+            .header(REFERER, "http://localhost/form")
+            .body(Body::empty())
+            .unwrap();
+        server_functions_router_with_config(None, cfg)
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    const HTML: &str = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+    #[tokio::test]
+    async fn form_submissions_are_redirected_to_the_referrer() {
+        let response = submit_form(ServeConfig::new(), "/api/referrer_redirect_ok", HTML).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[LOCATION], "http://localhost/form");
+
+        let cfg = ServeConfig::new().referrer_redirect(Some(StatusCode::SEE_OTHER));
+        let response = submit_form(cfg, "/api/referrer_redirect_ok", HTML).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[LOCATION], "http://localhost/form");
+    }
+
+    #[tokio::test]
+    async fn only_form_submissions_are_redirected_to_the_referrer() {
+        let response = submit_form(
+            ServeConfig::new(),
+            "/api/referrer_redirect_ok",
+            "application/json",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(LOCATION));
+
+        let cfg = ServeConfig::new().referrer_redirect(None);
+        let response = submit_form(cfg, "/api/referrer_redirect_ok", HTML).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(LOCATION));
+
+        // A server function that redirects itself keeps its own location
+        let response =
+            submit_form(ServeConfig::new(), "/api/referrer_redirect_location", HTML).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[LOCATION], "/done");
+    }
+
+    fn fails() -> Element {
+        Err(anyhow::anyhow!("database offline").into())
+    }
+
+    async fn render(cfg: ServeConfig) -> (Response, String) {
+        let state = FullstackState::new(cfg, fails);
+        let request = http::Request::get("/").body(Body::empty()).unwrap();
+        let (parts, body) = FullstackState::render_handler(State(state), request)
+            .await
+            .into_response()
+            .into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (
+            Response::from_parts(parts, Body::empty()),
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn error_pages_use_the_content_security_policy_nonce() {
+        let index = IndexHtml::new(
+            r#"<html><head><script src="/app.js"></script></head><body><div id="main"></div></body></html>"#,
+            "main",
+        )
+        .unwrap();
+        let cfg = ServeConfig::with_index_html(index)
+            .content_security_policy("script-src 'nonce-{nonce}'")
+            .error_page(
+                StatusClass::ServerError,
+                ErrorPage::Template("<h1>{status}</h1>".to_string()),
+            );
+
+        let (response, body) = render(cfg).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let policy = response.headers()[CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap();
+        let nonce = policy
+            .strip_prefix("script-src 'nonce-")
+            .and_then(|policy| policy.strip_suffix('\''))
+            .unwrap();
+        assert!(!nonce.is_empty());
+        assert!(body.contains(&format!(r#"<script nonce="{nonce}" src="/app.js">"#)));
+        assert!(body.contains("<h1>500</h1>"));
+    }
+
+    #[tokio::test]
+    async fn error_responses_without_an_error_page_use_the_content_security_policy() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only())
+            .content_security_policy("default-src 'self'");
+
+        let (response, _) = render(cfg).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            response.headers()[CONTENT_SECURITY_POLICY],
+            "default-src 'self'"
+        );
+    }
+
+    fn sets_a_link_header() -> Element {
+        use_hook(|| {
+            FullstackContext::current().unwrap().add_response_header(
+                LINK,
+                HeaderValue::from_static("</style.css>; rel=preload; as=style"),
+            )
+        });
+        VNode::empty()
+    }
+
+    #[tokio::test]
+    async fn preload_headers_are_kept_with_link_headers_from_the_app() {
+        let index = IndexHtml::new(
+            r#"<html><head><script type="module" src="/app.js"></script></head><body><div id="main"></div></body></html>"#,
+            "main",
+        )
+        .unwrap();
+        let cfg = ServeConfig::with_index_html(index).preload_headers(true);
+        let state = FullstackState::new(cfg, sets_a_link_header);
+        let request = http::Request::get("/").body(Body::empty()).unwrap();
+
+        let response = FullstackState::render_handler(State(state), request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let links = response
+            .headers()
+            .get_all(LINK)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            [
+                "</style.css>; rel=preload; as=style",
+                "</app.js>; rel=modulepreload"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn route_data_errors_are_sent_as_json() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only()).error_page(
+            StatusClass::ServerError,
+            ErrorPage::Template("<h1>{status}</h1>".to_string()),
+        );
+        let state = FullstackState::new(cfg, fails);
+        let request = http::Request::get("/?dx-route-data")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = FullstackState::render_handler(State(state), request).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["ServerError"]["code"], 500);
+        if cfg!(debug_assertions) {
+            let message = error["ServerError"]["message"].as_str().unwrap();
+            assert!(message.contains("database offline"));
+        } else {
+            assert_eq!(error["ServerError"]["message"], "Internal Server Error");
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct RequestId(String);
+
+    const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+    fn request_id(parts: &http::request::Parts) -> impl Future<Output = RequestId> {
+        let id = parts.headers[REQUEST_ID].to_str().unwrap().to_string();
+        async move { RequestId(id) }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn echo_request_id(
+        _: State<FullstackContext>,
+        _: Request,
+    ) -> Pin<Box<dyn Future<Output = Response>>> {
+        Box::pin(async { server_context::<RequestId>().unwrap().0.into_response() })
+    }
+
+    inventory::submit! {
+        ServerFunction::new(http::Method::GET, "/api/echo_request_id", || {
+            ServerFunction::make_handler(http::Method::GET, echo_request_id)
+        })
+    }
+
+    /// Sends the request id from the root context and from `server_context` back in response headers
+    fn sends_the_request_id() -> Element {
+        use_hook(|| {
+            let context = FullstackContext::current().unwrap();
+            let from_root = dioxus_core::consume_context::<RequestId>();
+            let from_server_context = server_context::<RequestId>().unwrap();
+            for (name, id) in [
+                ("x-root", from_root),
+                ("x-server-context", from_server_context),
+            ] {
+                context.add_response_header(
+                    HeaderName::from_static(name),
+                    HeaderValue::from_str(&id.0).unwrap(),
+                );
+            }
+        });
+        VNode::empty()
+    }
+
+    #[tokio::test]
+    async fn request_contexts_are_created_for_each_request() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only())
+            .request_context_provider(request_id);
+        let router = register_server_functions(Router::new())
+            .route("/", axum::routing::get(FullstackState::render_handler))
+            .with_state(FullstackState::new(cfg, sends_the_request_id));
+
+        for id in ["first", "second"] {
+            let request = http::Request::get("/")
+                .header(REQUEST_ID, id)
+                .body(Body::empty())
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["x-root"], id);
+            assert_eq!(response.headers()["x-server-context"], id);
+
+            let request = http::Request::get("/api/echo_request_id")
+                .header(REQUEST_ID, id)
+                .body(Body::empty())
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, id);
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use crate::FullstackState;
+use crate::{FullstackState, ServerContextProviders};
 use axum::{
+    body::Body,
     extract::{Request, State},
     response::Response,
     routing::MethodRouter,
 };
 use dioxus_fullstack_core::FullstackContext;
-use http::Method;
+use http::{Method, StatusCode};
 use std::{pin::Pin, prelude::rust_2024::Future};
 
 /// A function endpoint that can be called from the client.
@@ -73,77 +74,35 @@ impl ServerFunction {
 
 /// Native: run handler inside tokio LocalPool for !Send future support.
 #[cfg(not(target_arch = "wasm32"))]
+#[allow(clippy::type_complexity)]
 fn make_handler_native(
     method: Method,
     handler: fn(State<FullstackContext>, Request) -> Pin<Box<dyn Future<Output = Response>>>,
 ) -> MethodRouter<FullstackState> {
-    use axum::body::Body;
-    use http::StatusCode;
-
     axum::routing::method_routing::on(
         method
             .try_into()
             .expect("MethodFilter only supports standard HTTP methods"),
         move |state: State<FullstackState>, request: Request| async move {
-            use tracing::Instrument;
             let current_span = tracing::Span::current();
-            let result = state.rt.spawn_pinned(move || async move {
-                use http::header::{ACCEPT, LOCATION, REFERER};
-
-                let (parts, body) = request.into_parts();
-                let server_context = FullstackContext::new(parts.clone());
-                let request = axum::extract::Request::from_parts(parts, body);
-
-                let referrer = request.headers().get(REFERER).cloned();
-                let accepts_html = request
-                    .headers()
-                    .get(ACCEPT)
-                    .and_then(|v| v.to_str().ok())
-                    .map(|v| v.contains("text/html"))
-                    .unwrap_or(false);
-
-                server_context
-                    .clone()
-                    .scope(async move {
-                        let mut response = handler(State(server_context), request)
-                            .instrument(current_span)
-                            .await;
-
-                        let server_context = FullstackContext::current().expect(
-                            "Server context should be available inside the server context scope",
-                        );
-
-                        let headers = server_context.take_response_headers();
-                        if let Some(headers) = headers {
-                            response.headers_mut().extend(headers);
-                        }
-
-                        if accepts_html {
-                            if let Some(referrer) = referrer {
-                                let has_location = response.headers().get(LOCATION).is_some();
-                                if !has_location {
-                                    *response.status_mut() = StatusCode::FOUND;
-                                    response.headers_mut().insert(LOCATION, referrer);
-                                }
-                            }
-                        }
-
-                        response
-                    })
-                    .await
-            })
-            .await;
+            let referrer_redirect = state.config.referrer_redirect;
+            let context_providers = state.context_providers.clone();
+            let result = state
+                .rt
+                .spawn_pinned(move || {
+                    handle_server_function(
+                        handler,
+                        request,
+                        referrer_redirect,
+                        context_providers,
+                        current_span,
+                    )
+                })
+                .await;
 
             match result {
                 Ok(response) => response,
-                Err(err) => Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::new(if cfg!(debug_assertions) {
-                        format!("Server function panicked: {}", err)
-                    } else {
-                        "Internal Server Error".to_string()
-                    }))
-                    .unwrap(),
+                Err(err) => panic_response(err),
             }
         },
     )
@@ -179,6 +138,7 @@ impl<F: Future> Future for AssertSend<F> {
 /// WASM: run handler directly — no thread pool needed.
 /// Workers are single-threaded per isolate.
 #[cfg(target_arch = "wasm32")]
+#[allow(clippy::type_complexity)]
 fn make_handler_wasm(
     method: Method,
     handler: fn(State<FullstackContext>, Request) -> Pin<Box<dyn Future<Output = Response>>>,
@@ -187,34 +147,232 @@ fn make_handler_wasm(
         method
             .try_into()
             .expect("MethodFilter only supports standard HTTP methods"),
-        move |request: Request| {
+        move |state: State<FullstackState>, request: Request| {
+            install_panic_hook();
+            let fallback = request.extensions().get::<PanicFallback>().cloned();
+            let server_function = CatchPanic {
+                future: handle_server_function(
+                    handler,
+                    request,
+                    state.config.referrer_redirect,
+                    state.context_providers.clone(),
+                    tracing::Span::current(),
+                ),
+                fallback,
+            };
             AssertSend(async move {
-                let (parts, body) = request.into_parts();
-                let server_context = FullstackContext::new(parts.clone());
-                let request = axum::extract::Request::from_parts(parts, body);
-
-                server_context
-                    .clone()
-                    .scope(async move {
-                        let mut response =
-                            handler(State(server_context.clone()), request).await;
-
-                        // Apply any response headers set during the handler
-                        if let Some(ctx) = FullstackContext::current() {
-                            let headers = ctx.take_response_headers();
-                            if let Some(headers) = headers {
-                                response.headers_mut().extend(headers);
-                            }
-                        }
-
-                        response
-                    })
-                    .await
+                match server_function.await {
+                    Ok(response) => response,
+                    Err(panic) => panic_response(panic),
+                }
             })
         },
     )
 }
 
+/// Receives the `500` response for a server function that panicked on wasm32.
+///
+/// Workers build with `panic = "abort"` on stable Rust, so a panic traps the isolate before the server function can
+/// return a response and the client only sees a dropped request. The panic hook calls the fallback attached to the
+/// request with the response before the isolate traps. The fallback runs synchronously inside the panic hook, so it
+/// should hand the response straight to JavaScript, for example by resolving a promise that the Worker entry point
+/// races against the router.
+///
+/// Builds that unwind, like nightly builds with `-Zbuild-std` and `panic = "unwind"`, catch the panic and return the
+/// `500` response from the router instead.
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone)]
+pub struct PanicFallback(std::rc::Rc<dyn Fn(http::Response<String>)>);
+
+// SAFETY: Without the atomics target feature, wasm32-unknown-unknown is single-threaded. The fallback may hold
+// JavaScript values that are not `Send` or `Sync`, but there are no other threads it could be moved to or shared with.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl Send for PanicFallback {}
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl Sync for PanicFallback {}
+
+#[cfg(target_arch = "wasm32")]
+impl PanicFallback {
+    /// Create a fallback that receives the `500` response for a panic
+    pub fn new(fallback: impl Fn(http::Response<String>) + 'static) -> Self {
+        Self(std::rc::Rc::new(fallback))
+    }
+
+    /// Attach the fallback to a request before it is passed to the router
+    pub fn attach<B>(self, request: &mut http::Request<B>)
+    where
+        Self: Send + Sync,
+    {
+        request.extensions_mut().insert(self);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+thread_local! {
+    /// The fallbacks of the server functions being polled on this thread. Panics are only reported by the panic hook
+    /// while a server function is running
+    static POLLING_SERVER_FUNCTIONS: std::cell::RefCell<Vec<Option<PanicFallback>>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Install a panic hook that reports panics in server functions before the previous hook runs.
+///
+/// With `panic = "abort"`, the isolate traps right after the hook, so [`CatchPanic`] never sees the panic. The hook
+/// logs the panic through tracing and passes the `500` response to the [`PanicFallback`] of the request, if there is
+/// one. Builds that unwind only log the panic, because [`CatchPanic`] returns the `500` response from the router and
+/// the fallback would send a second response for the same request.
+#[cfg(target_arch = "wasm32")]
+fn install_panic_hook() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // The hook may run while the stack is borrowed if the panic happened while it was updated
+            let polling = POLLING_SERVER_FUNCTIONS.with(|polling| {
+                polling
+                    .try_borrow()
+                    .ok()
+                    .and_then(|polling| polling.last().cloned())
+            });
+            if let Some(fallback) = polling {
+                tracing::error!("Server function panicked: {info}");
+                // Builds that unwind return the response from `CatchPanic` instead
+                let fallback = fallback.filter(|_| cfg!(panic = "abort"));
+                if let Some(PanicFallback(fallback)) = fallback {
+                    let message = info.payload_as_str().unwrap_or("Box<dyn Any>");
+                    fallback(
+                        http::Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(panic_message(message))
+                            .unwrap(),
+                    );
+                }
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// A future that catches panics while the inner future is polled and resolves to the panic message instead.
+///
+/// This only catches panics in builds that unwind, which on wasm32 needs nightly `-Zbuild-std` with
+/// `panic = "unwind"`. Stable builds abort, and the panic hook passes the response to the [`PanicFallback`] instead.
+/// After a panic, the inner future is never polled again.
+#[cfg(target_arch = "wasm32")]
+struct CatchPanic<F> {
+    future: F,
+    fallback: Option<PanicFallback>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<F: Future> Future for CatchPanic<F> {
+    type Output = Result<F::Output, String>;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        // SAFETY: We are not moving `future`, only projecting through the pin. `fallback` is never pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        POLLING_SERVER_FUNCTIONS.with(|polling| polling.borrow_mut().push(this.fallback.clone()));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| future.poll(cx)));
+        POLLING_SERVER_FUNCTIONS.with(|polling| polling.borrow_mut().pop());
+
+        match result {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => std::task::Poll::Ready(Err(payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".to_string()))),
+        }
+    }
+}
+
+/// Run a server function inside a [`FullstackContext`] for the request. This is shared by every target, so server
+/// functions behave the same on native and wasm32:
+/// - The context providers from the [`ServeConfig`](crate::ServeConfig), including the per-request providers, are
+///   available with [`server_context`](crate::server_context)
+/// - Request extensions, like the [`WorkerBindings`](crate::WorkerBindings) attached by the Worker entry point, are
+///   moved into the [`FullstackContext`]
+/// - Headers set on the [`FullstackContext`] are merged into the response
+/// - html form submissions are redirected back to the referrer with the status from
+///   [`ServeConfig::referrer_redirect`](crate::ServeConfig::referrer_redirect)
+#[allow(clippy::type_complexity)]
+async fn handle_server_function(
+    handler: fn(State<FullstackContext>, Request) -> Pin<Box<dyn Future<Output = Response>>>,
+    request: Request,
+    referrer_redirect: Option<StatusCode>,
+    context_providers: ServerContextProviders,
+    span: tracing::Span,
+) -> Response {
+    use http::header::{ACCEPT, LOCATION, REFERER};
+    use tracing::Instrument;
+
+    let (mut parts, body) = request.into_parts();
+    let context_providers = context_providers.for_request(&parts).await;
+    parts.extensions.insert(context_providers);
+    // Futures passed to `defer` start once the response is created and this guard is dropped
+    let _after_response = crate::defer::defer_after_response(&mut parts);
+    let server_context = FullstackContext::new(parts.clone());
+    let request = axum::extract::Request::from_parts(parts, body);
+
+    let referrer = request.headers().get(REFERER).cloned();
+    let accepts_html = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("text/html"))
+        .unwrap_or(false);
+
+    server_context
+        .clone()
+        .scope(async move {
+            let mut response = handler(State(server_context), request)
+                .instrument(span)
+                .await;
+
+            let server_context = FullstackContext::current()
+                .expect("Server context should be available inside the server context scope");
+
+            let headers = server_context.take_response_headers();
+            if let Some(headers) = headers {
+                response.headers_mut().extend(headers);
+            }
+
+            if let (Some(status), Some(referrer), true) =
+                (referrer_redirect, referrer, accepts_html)
+            {
+                let has_location = response.headers().get(LOCATION).is_some();
+                if !has_location {
+                    *response.status_mut() = status;
+                    response.headers_mut().insert(LOCATION, referrer);
+                }
+            }
+
+            response
+        })
+        .await
+}
+
+/// The response for a server function that panicked
+fn panic_response(panic: impl std::fmt::Display) -> Response {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::new(panic_message(panic)))
+        .unwrap()
+}
+
+/// The body of the response for a server function that panicked. The panic is only included in debug builds
+fn panic_message(panic: impl std::fmt::Display) -> String {
+    if cfg!(debug_assertions) {
+        format!("Server function panicked: {}", panic)
+    } else {
+        "Internal Server Error".to_string()
+    }
+}
+
 impl inventory::Collect for ServerFunction {
     #[inline]
     fn registry() -> &'static inventory::Registry {
@@ -1,14 +1,21 @@
 //! A shared pool of renderers for efficient server side rendering.
+use crate::compression::deflate_raw;
+use crate::document::{CspNonce, NonceAttribute};
 use crate::isrg::{
     CachedRender, IncrementalRenderer, IncrementalRendererConfig, IncrementalRendererError,
     RenderFreshness,
 };
-use crate::streaming::{Mount, StreamingRenderer};
-use crate::{document::ServerDocument, ServeConfig};
+use crate::render_mode::{FragmentBoundary, FragmentTarget, RenderMode};
+use crate::streaming::{BoundaryScripts, Mount, StreamingRenderer};
+use crate::{
+    document::ServerDocument, ErrorPage, ErrorPageProps, HydrationPayload, ServeConfig,
+    ServerContextProviders, StatusClass,
+};
+use bytes::Bytes;
 use dioxus_cli_config::base_path;
 use dioxus_core::{
-    consume_context, has_context, try_consume_context, DynamicNode, ErrorContext, Runtime, ScopeId,
-    SuspenseContext, TemplateNode, VNode, VirtualDom,
+    consume_context, has_context, try_consume_context, CapturedError, DynamicNode, ErrorContext,
+    Runtime, ScopeId, SuspenseContext, TemplateNode, VNode, VirtualDom,
 };
 use dioxus_fullstack_core::{history::provide_fullstack_history_context, HttpError, ServerFnError};
 use dioxus_fullstack_core::{FullstackContext, StreamingStatus};
@@ -17,18 +24,62 @@ use dioxus_router::ParseRouteError;
 use dioxus_ssr::Renderer;
 use futures_channel::mpsc::Sender;
 use futures_util::{Stream, StreamExt};
-use http::{request::Parts, HeaderMap, StatusCode};
+use http::{request::Parts, HeaderMap, HeaderName, HeaderValue, StatusCode};
 use std::{
     collections::HashMap,
     fmt::Write,
     iter::Peekable,
     rc::Rc,
     sync::{Arc, RwLock},
+    time::Duration,
 };
-use tokio_util::task::LocalPoolHandle;
+use tracing::{field::Empty, Instrument};
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+#[cfg(not(target_arch = "wasm32"))]
+use {std::time::Instant, tokio_util::task::LocalPoolHandle};
 
 use crate::StreamingMode;
 
+/// The runtime renders are spawned on. The virtual dom is not `Send`, so on native each render runs on one of a pool of
+/// single threaded runtimes.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) type RenderRuntime = LocalPoolHandle;
+
+/// The runtime renders are spawned on. wasm32 only has one thread, so renders are spawned onto the executor that
+/// handles the request.
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone, Default)]
+pub(crate) struct RenderRuntime;
+
+#[cfg(not(target_arch = "wasm32"))]
+type RenderTask = tokio::task::JoinHandle<()>;
+
+#[cfg(target_arch = "wasm32")]
+type RenderTask = futures_util::future::AbortHandle;
+
+/// Spawn a render onto the runtime. The future is created on the thread it runs on so it doesn't need to be `Send`
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn_render<F: std::future::Future<Output = ()> + 'static>(
+    rt: &RenderRuntime,
+    create_render_future: impl FnOnce() -> F + Send + 'static,
+) -> RenderTask {
+    rt.spawn_pinned(create_render_future)
+}
+
+/// Spawn a render onto the runtime. The future is created on the thread it runs on so it doesn't need to be `Send`
+#[cfg(target_arch = "wasm32")]
+fn spawn_render<F: std::future::Future<Output = ()> + 'static>(
+    _: &RenderRuntime,
+    create_render_future: impl FnOnce() -> F + Send + 'static,
+) -> RenderTask {
+    let (render, abort_handle) = futures_util::future::abortable(create_render_future());
+    wasm_bindgen_futures::spawn_local(async move {
+        _ = render.await;
+    });
+    abort_handle
+}
+
 /// Errors that can occur during server side rendering before the initial chunk is sent down
 pub enum SSRError {
     /// An error from the incremental renderer. This should result in a 500 code
@@ -40,10 +91,98 @@ pub enum SSRError {
     },
 }
 
+impl SSRError {
+    /// The props for the error page of the error. The internal details of the error are only included in debug builds
+    pub(crate) fn into_error_page_props(self) -> ErrorPageProps {
+        self.error_page_props(cfg!(debug_assertions))
+    }
+
+    fn error_page_props(self, include_details: bool) -> ErrorPageProps {
+        match self {
+            SSRError::Incremental(err) => ErrorPageProps {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: None,
+                details: include_details.then(|| err.to_string()),
+            },
+            SSRError::HttpError { status, message } => ErrorPageProps {
+                status,
+                message,
+                details: None,
+            },
+        }
+    }
+}
+
+/// Reads the hydration data blocks into the globals the client reads the hydration data from. The script is
+/// constant, so it can be allowed by a hash in the `Content-Security-Policy`
+const HYDRATION_DATA_BLOCK_READER: &str = r#"(function(){function block(id){var el=document.getElementById(id);return el?el.textContent:undefined}function debug(key){var data=block("dx-hydration-debug");return data?JSON.parse(data)[key]:undefined}function global(name,get){Object.defineProperty(window,name,{configurable:true,get:get})}global("initial_dioxus_hydration_data",function(){return block("dx-hydration")});global("initial_dioxus_hydration_debug_types",function(){return debug("types")});global("initial_dioxus_hydration_debug_locations",function(){return debug("locations")})})();"#;
+
+/// The placeholder for the CSP nonce in routes that are stored in the incremental cache
+const CSP_NONCE_PLACEHOLDER: &str = "{{dioxus-csp-nonce}}";
+
 /// A suspense boundary that is pending with a placeholder in the client
 struct PendingSuspenseBoundary {
     mount: Mount,
     children: Vec<ScopeId>,
+    /// The time the placeholder for the boundary was rendered
+    pending_since: Instant,
+    /// The components the boundary is waiting on. This is only collected when suspense diagnostics are enabled
+    components: Option<String>,
+}
+
+/// The time it took a suspense boundary to resolve after its placeholder was rendered
+struct SuspenseBoundaryTiming {
+    mount: String,
+    components: String,
+    time_to_resolve: Duration,
+}
+
+/// The durations of each phase of a server side render. Phases are recorded as fields (in milliseconds) on the
+/// render span and can be sent to the client in a `Server-Timing` header.
+struct RenderTimings {
+    span: tracing::Span,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl RenderTimings {
+    fn new(route: &str) -> Self {
+        Self {
+            span: tracing::info_span!(
+                "ssr",
+                route,
+                cache = Empty,
+                build = Empty,
+                rebuild = Empty,
+                suspense = Empty,
+                head = Empty,
+                first_flush = Empty,
+                total = Empty,
+                hydration_bytes = Empty,
+                dropped_chunks = Empty,
+            ),
+            phases: Vec::new(),
+        }
+    }
+
+    /// Record the duration of a phase that started at `start`
+    fn record(&mut self, phase: &'static str, start: Instant) {
+        let duration = start.elapsed();
+        self.span.record(phase, duration.as_secs_f64() * 1000.0);
+        self.phases.push((phase, duration));
+    }
+
+    /// Write the phases recorded so far into a `Server-Timing` header
+    fn write(&self, headers: &mut HeaderMap) {
+        let server_timing = self
+            .phases
+            .iter()
+            .map(|(phase, duration)| format!("{phase};dur={:.3}", duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(value) = HeaderValue::from_str(&server_timing) {
+            headers.insert(HeaderName::from_static("server-timing"), value);
+        }
+    }
 }
 
 pub(crate) struct SsrRendererPool {
@@ -61,11 +200,15 @@ impl SsrRendererPool {
     }
 
     /// Look for a cached route in the incremental cache and send it into the render channel if it exists
+    ///
+    /// Cached routes are stored with a placeholder in place of the CSP nonce. The placeholder is replaced with the nonce
+    /// of the current request.
     fn check_cached_route(
         &self,
         route: &str,
-        render_into: &mut Sender<Result<String, IncrementalRendererError>>,
-    ) -> Option<RenderFreshness> {
+        render_into: &mut Sender<Result<Bytes, IncrementalRendererError>>,
+        nonce: Option<&str>,
+    ) -> Option<(RenderFreshness, Vec<String>)> {
         let incremental = self.incremental_cache.as_ref()?;
 
         if let Ok(mut incremental) = incremental.write() {
@@ -74,13 +217,19 @@ impl SsrRendererPool {
                     let CachedRender {
                         freshness,
                         response,
+                        preload_links,
                         ..
                     } = cached_render;
                     _ = render_into.start_send(
                         String::from_utf8(response.to_vec())
+                            .map(|html| match nonce {
+                                Some(nonce) => html.replace(CSP_NONCE_PLACEHOLDER, nonce),
+                                None => html,
+                            })
+                            .map(Bytes::from)
                             .map_err(|err| IncrementalRendererError::Other(err.into())),
                     );
-                    return Some(freshness);
+                    return Some((freshness, preload_links.to_vec()));
                 }
                 Err(e) => {
                     tracing::error!("Failed to get route \"{route}\" from incremental cache: {e}");
@@ -96,26 +245,28 @@ impl SsrRendererPool {
     /// The streaming is canceled when the stream the function returns is dropped
     pub(crate) async fn render_to(
         self: Arc<Self>,
-        parts: Parts,
+        mut parts: Parts,
+        mode: RenderMode,
         cfg: &ServeConfig,
-        rt: &LocalPoolHandle,
+        rt: &RenderRuntime,
+        context_providers: ServerContextProviders,
         virtual_dom_factory: impl FnOnce() -> VirtualDom + Send + Sync + 'static,
     ) -> Result<
         (
             HttpError,
             HeaderMap,
             RenderFreshness,
-            impl Stream<Item = Result<String, IncrementalRendererError>>,
+            impl Stream<Item = Result<Bytes, IncrementalRendererError>>,
         ),
         SSRError,
     > {
         struct ReceiverWithDrop {
-            receiver: futures_channel::mpsc::Receiver<Result<String, IncrementalRendererError>>,
-            cancel_task: Option<tokio::task::JoinHandle<()>>,
+            receiver: futures_channel::mpsc::Receiver<Result<Bytes, IncrementalRendererError>>,
+            cancel_task: Option<RenderTask>,
         }
 
         impl Stream for ReceiverWithDrop {
-            type Item = Result<String, IncrementalRendererError>;
+            type Item = Result<Bytes, IncrementalRendererError>;
 
             fn poll_next(
                 mut self: std::pin::Pin<&mut Self>,
@@ -134,6 +285,8 @@ impl SsrRendererPool {
             }
         }
 
+        let render_start = Instant::now();
+
         let route = parts
             .uri
             .path_and_query()
@@ -143,19 +296,43 @@ impl SsrRendererPool {
             })?
             .to_string();
 
-        let (mut into, rx) =
-            futures_channel::mpsc::channel::<Result<String, IncrementalRendererError>>(1000);
+        let server_timing = cfg.server_timing;
+        let mut timings = RenderTimings::new(&route);
+
+        // Generate a nonce for the scripts on this page if CSP nonces are enabled. The nonce is inserted into the
+        // request extensions so it is available from the FullstackContext
+        let nonce = cfg.csp_nonce.then(CspNonce::generate);
+        if let Some(nonce) = &nonce {
+            parts.extensions.insert(nonce.clone());
+        }
+        let nonce = nonce.map(|nonce| nonce.0);
+
+        let (mut into, rx) = futures_channel::mpsc::channel::<
+            Result<Bytes, IncrementalRendererError>,
+        >(cfg.streaming_buffer_size);
 
         let (initial_result_tx, initial_result_rx) = futures_channel::oneshot::channel();
 
+        // Partial renders are cached separately from the full document of the route
+        let cache_key = mode.cache_key(&route);
+
         // before we even spawn anything, we can check synchronously if we have the route cached
-        if let Some(freshness) = self.check_cached_route(&route, &mut into) {
+        let cache_start = Instant::now();
+        let cached = self.check_cached_route(&cache_key, &mut into, nonce.as_deref());
+        timings.record("cache", cache_start);
+        if let Some((freshness, preload_links)) = cached {
+            let mut headers = HeaderMap::new();
+            cfg.write_content_security_policy(&mut headers, nonce.as_deref());
+            cfg.write_preload_headers(&mut headers, &preload_links);
+            if server_timing {
+                timings.write(&mut headers);
+            }
             return Ok((
                 HttpError {
                     status: StatusCode::OK,
                     message: None,
                 },
-                HeaderMap::new(),
+                headers,
                 freshness,
                 ReceiverWithDrop {
                     receiver: rx,
@@ -172,12 +349,28 @@ impl SsrRendererPool {
             .unwrap_or_else(Self::pre_renderer);
 
         let myself = self.clone();
-        let streaming_mode = cfg.streaming_mode;
+        // Partial renders are sent in a single chunk once all suspense has resolved
+        let streaming_mode = match mode {
+            RenderMode::Document => cfg.streaming_mode,
+            RenderMode::Fragment(_) | RenderMode::Data => StreamingMode::Disabled,
+        };
 
         let cfg = cfg.clone();
+        let render_span = timings.span.clone();
         let create_render_future = move || async move {
+            let build_start = Instant::now();
+            // Create the contexts for this request and make them available to the app and any server functions it
+            // calls while rendering
+            let context_providers = context_providers.for_request(&parts).await;
+            parts.extensions.insert(context_providers.clone());
+            // Futures passed to `defer` while rendering start once the render is finished
+            let _after_response = crate::defer::defer_after_response(&mut parts);
+
             let mut virtual_dom = virtual_dom_factory();
-            let document = Rc::new(ServerDocument::default());
+            for provider in context_providers.iter() {
+                virtual_dom.insert_any_root_context(provider());
+            }
+            let document = Rc::new(ServerDocument::with_nonce(nonce.clone()));
             virtual_dom.provide_root_context(document.clone());
 
             // If there is a base path, trim the base path from the route and add the base path formatting to the
@@ -207,11 +400,59 @@ impl SsrRendererPool {
                 );
             });
 
+            timings.record("build", build_start);
+
             // rebuild the virtual dom
+            let rebuild_start = Instant::now();
             virtual_dom.rebuild_in_place();
+            timings.record("rebuild", rebuild_start);
+
+            // The initial result and the channel are taken by whichever part of the render sends the status
+            let mut initial_result_tx = Some(initial_result_tx);
+            let mut into = Some(into);
+
+            // If the head is flushed early, the status is committed as soon as the first render finishes and the
+            // static head is sent before waiting for suspense. Later errors can't change the status
+            let mut early_stream = None;
+            if cfg.early_head_flush
+                && mode == RenderMode::Document
+                && streaming_mode == StreamingMode::OutOfOrder
+            {
+                if let Some(err) = Self::root_error(&cfg, &virtual_dom) {
+                    _ = initial_result_tx.take().unwrap().send(Err(err));
+                    return;
+                }
+
+                let head_start = Instant::now();
+                let mut early_head = String::new();
+                if let Err(err) = Self::render_early_head(&cfg, &mut early_head, nonce.as_deref()) {
+                    _ = initial_result_tx
+                        .take()
+                        .unwrap()
+                        .send(Err(SSRError::Incremental(err)));
+                    return;
+                }
+                timings.record("head", head_start);
+
+                let response_head = Self::response_head(
+                    &cfg,
+                    &streaming_context,
+                    &document,
+                    nonce.as_deref(),
+                    &timings,
+                );
+                _ = initial_result_tx.take().unwrap().send(Ok(response_head));
+                early_stream = Some(Arc::new(StreamingRenderer::new(
+                    early_head,
+                    into.take().unwrap(),
+                    nonce.clone(),
+                    cfg.streaming_flush_policy,
+                )));
+            }
 
             // If streaming is disabled, wait for the virtual dom to finish all suspense work
             // before rendering anything
+            let suspense_start = Instant::now();
             if streaming_mode == StreamingMode::Disabled {
                 virtual_dom.wait_for_suspense().await;
             } else {
@@ -236,79 +477,120 @@ impl SsrRendererPool {
                 }
             }
 
-            // check if there are any errors from the root error boundary
-            let error = virtual_dom.in_scope(ScopeId::ROOT_ERROR_BOUNDARY, || {
-                consume_context::<ErrorContext>().error()
-            });
-
-            if let Some(error) = error {
-                let mut status_code = None;
-                let mut out_message = None;
-
-                // If the errors include an `HttpError` or `StatusCode` or `ServerFnError`, we need
-                // to try and return the appropriate status code
-                if let Some(error) = error.downcast_ref::<HttpError>() {
-                    status_code = Some(error.status);
-                    out_message = error.message.clone();
-                }
-
-                if let Some(error) = error.downcast_ref::<StatusCode>() {
-                    status_code = Some(*error);
+            timings.record("suspense", suspense_start);
+
+            // If the error is found after the head was flushed early, the status was already sent. The error page is
+            // rendered into the body of the streamed document instead
+            if let Some(err) = Self::root_error(&cfg, &virtual_dom) {
+                match (initial_result_tx.take(), &early_stream) {
+                    (Some(initial_result_tx), _) => _ = initial_result_tx.send(Err(err)),
+                    (None, Some(stream)) => {
+                        let props = err.into_error_page_props();
+                        tracing::error!(
+                            "Failed to render page after the head was flushed: {}",
+                            props.fallback_message()
+                        );
+                        let mut error_page = String::new();
+                        if let Err(err) = Self::render_streamed_error_page(
+                            &cfg,
+                            &mut error_page,
+                            nonce.as_deref(),
+                            props,
+                        ) {
+                            tracing::error!("Failed to render error page: {err}");
+                        }
+                        stream.close_with_error(None, error_page).await;
+                    }
+                    (None, None) => {}
                 }
+                return;
+            }
 
-                // todo - the user is allowed to return anything that impls `From<ServerFnError>`
-                // we need to eventually be able to downcast that and get the status code from it
-                if let Some(ServerFnError::ServerError { message, code, .. }) = error.downcast_ref()
-                {
-                    status_code = Some(
-                        (*code)
-                            .try_into()
-                            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
-                    );
-
-                    out_message = Some(message.clone());
+            // Wait long enough to assemble the `<head>` of the document before starting to stream. Partial renders
+            // skip the shell and render the whole response up front instead. If the head was flushed early, only
+            // the head elements from the app are left
+            let mut pre_body = String::new();
+            let partial = match &mode {
+                RenderMode::Document if early_stream.is_some() => {
+                    if let Err(err) = Self::render_late_head(&cfg, &mut pre_body, &virtual_dom) {
+                        tracing::error!("Failed to render the head after it was flushed: {err}");
+                    }
+                    None
                 }
-
-                // If there was an error while routing, return the error with a 404 status
-                // Return a routing error if any of the errors were a routing error
-                if let Some(routing_error) = error.downcast_ref::<ParseRouteError>().cloned() {
-                    status_code = Some(StatusCode::NOT_FOUND);
-                    out_message = Some(routing_error.to_string());
+                RenderMode::Document => {
+                    let head_start = Instant::now();
+                    if let Err(err) = Self::render_head(&cfg, &mut pre_body, &virtual_dom) {
+                        let initial_result_tx = initial_result_tx.take().unwrap();
+                        _ = initial_result_tx.send(Err(SSRError::Incremental(err)));
+                        return;
+                    }
+                    timings.record("head", head_start);
+                    None
                 }
-
-                // If we captured anything that produces a status code, we should return that status code.
-                if let Some(status_code) = status_code {
-                    _ = initial_result_tx.send(Err(SSRError::HttpError {
-                        status: status_code,
-                        message: out_message,
-                    }));
-                    return;
+                RenderMode::Fragment(target) => {
+                    match Self::render_fragment(
+                        &cfg,
+                        &mut renderer,
+                        &virtual_dom,
+                        &document,
+                        nonce.as_deref(),
+                        target,
+                    ) {
+                        Ok(fragment) => Some(fragment),
+                        Err(err) => {
+                            _ = initial_result_tx.take().unwrap().send(Err(err));
+                            return;
+                        }
+                    }
                 }
+                RenderMode::Data => Some(Self::render_route_data(&cfg, &virtual_dom)),
+            };
 
-                _ = initial_result_tx.send(Err(SSRError::Incremental(
-                    IncrementalRendererError::Other(error),
-                )));
-
-                return;
+            // Now that we handled any errors from rendering, we can send the initial ok result
+            if let Some(initial_result_tx) = initial_result_tx.take() {
+                let response_head = Self::response_head(
+                    &cfg,
+                    &streaming_context,
+                    &document,
+                    nonce.as_deref(),
+                    &timings,
+                );
+                _ = initial_result_tx.send(Ok(response_head));
             }
 
-            // Check the FullstackContext in case the user set the statuscode manually or via a layout.
-            let http_status = streaming_context.current_http_status();
-            let headers = streaming_context
-                .take_response_headers()
-                .unwrap_or_default();
-
-            // Now that we handled any errors from rendering, we can send the initial ok result
-            _ = initial_result_tx.send(Ok((http_status, headers)));
+            if let Some(partial) = partial {
+                if let Some(incremental) = &self.incremental_cache {
+                    let cached_render = match &nonce {
+                        Some(nonce) => partial.replace(nonce, CSP_NONCE_PLACEHOLDER),
+                        None => partial.clone(),
+                    };
+                    if let Ok(mut incremental) = incremental.write() {
+                        let _ = incremental.cache_with_preload_links(
+                            cache_key,
+                            cached_render,
+                            document.preload_links(),
+                        );
+                    }
+                }
+                _ = into.take().unwrap().start_send(Ok(partial.into()));
+                timings.record("total", render_start);
 
-            // Wait long enough to assemble the `<head>` of the document before starting to stream
-            let mut pre_body = String::new();
-            if let Err(err) = Self::render_head(&cfg, &mut pre_body, &virtual_dom) {
-                _ = into.start_send(Err(err));
+                myself.renderers.write().unwrap().push(renderer);
                 return;
             }
 
-            let stream = Arc::new(StreamingRenderer::new(pre_body, into));
+            let stream = match early_stream {
+                Some(stream) => {
+                    stream.render(pre_body).await;
+                    stream
+                }
+                None => Arc::new(StreamingRenderer::new(
+                    pre_body,
+                    into.take().unwrap(),
+                    nonce.clone(),
+                    cfg.streaming_flush_policy,
+                )),
+            };
             let scope_to_mount_mapping = Arc::new(RwLock::new(HashMap::new()));
 
             renderer.pre_render = true;
@@ -318,28 +600,59 @@ impl SsrRendererPool {
                 renderer.set_render_components(Self::streaming_render_component_callback(
                     stream,
                     scope_to_mount_mapping,
+                    cfg.suspense_diagnostics.is_some(),
                 ));
             }
+            let mut suspense_boundary_timings = Vec::new();
 
+            // Once the initial chunk is sent, errors are sent to the client in band and the document is closed
+            let mut close_document = String::new();
+            _ = Self::render_after_body(&cfg, &mut close_document, nonce.as_deref());
             macro_rules! throw_error {
                 ($e:expr) => {
-                    stream.close_with_error($e);
+                    tracing::error!("Failed to render page after streaming started: {}", $e);
+                    stream.close_with_error(None, &close_document).await;
+                    return;
+                };
+                ($e:expr, $failed_placeholder:expr) => {
+                    let error = $e;
+                    tracing::error!("Failed to render suspense boundary: {}", error);
+                    stream.close_with_error(
+                        Some(($failed_placeholder, Self::serialize_error(&cfg, error))),
+                        &close_document,
+                    ).await;
                     return;
                 };
             }
 
             // Render the initial frame with loading placeholders
+            let first_flush_start = Instant::now();
             let mut initial_frame = renderer.render(&virtual_dom);
 
             // Along with the initial frame, we render the html after the main element, but before the body tag closes. This should include the script that starts loading the wasm bundle.
-            if let Err(err) = Self::render_after_main(&cfg, &mut initial_frame, &virtual_dom) {
-                throw_error!(err);
-            }
-            stream.render(initial_frame);
+            let mut hydration_bytes =
+                match Self::render_after_reader(&cfg, &mut initial_frame, &virtual_dom) {
+                    Ok(size) => size,
+                    Err(err) => {
+                        throw_error!(err);
+                    }
+                };
+            Self::check_hydration_budget(cfg.hydration_boundary_budget, hydration_bytes, || {
+                format!("The initial hydration data for \"{route}\"")
+            });
+            stream.render(initial_frame).await;
+            // The initial frame is always sent right away so the page can be shown while suspense resolves
+            stream.flush().await;
+            timings.record("first_flush", first_flush_start);
+
+            // Resolved boundaries are rendered into the same buffer which is coalesced into the stream
+            let mut resolved_chunk = String::new();
 
             // After the initial render, we need to resolve suspense
             while virtual_dom.suspended_tasks_remaining() {
-                virtual_dom.wait_for_suspense_work().await;
+                stream
+                    .flush_while(virtual_dom.wait_for_suspense_work())
+                    .await;
                 let resolved_suspense_nodes = virtual_dom.render_suspense_immediate().await;
 
                 // Just rerender the resolved nodes
@@ -350,23 +663,80 @@ impl SsrRendererPool {
                     };
                     // If the suspense boundary was immediately removed, it may not have a mount. We can just skip resolving it
                     if let Some(pending_suspense_boundary) = pending_suspense_boundary {
-                        let mut resolved_chunk = String::new();
-                        // After we replace the placeholder in the dom with javascript, we need to send down the resolved data so that the client can hydrate the node
-                        let render_suspense = |into: &mut String| {
-                            renderer.reset_hydration();
-                            renderer.render_scope(into, &virtual_dom, scope)
-                        };
-                        let resolved_data = Self::serialize_server_data(&virtual_dom, scope);
-                        if let Err(err) = stream.replace_placeholder(
-                            pending_suspense_boundary.mount,
-                            render_suspense,
-                            resolved_data,
-                            &mut resolved_chunk,
-                        ) {
-                            throw_error!(IncrementalRendererError::RenderError(err));
+                        let boundary_start = Instant::now();
+                        let boundary_span = tracing::debug_span!(
+                            "suspense_boundary",
+                            mount = %pending_suspense_boundary.mount,
+                            components = pending_suspense_boundary.components.as_deref(),
+                            duration = Empty,
+                        );
+                        // The span is only entered for the synchronous work. Other tasks on this thread run while
+                        // the chunk is sent, so the send is instrumented instead
+                        let placeholder = boundary_span.in_scope(|| {
+                            if let Some(threshold) = cfg.suspense_diagnostics {
+                                let timing = SuspenseBoundaryTiming {
+                                    mount: pending_suspense_boundary.mount.to_string(),
+                                    components: pending_suspense_boundary
+                                        .components
+                                        .clone()
+                                        .unwrap_or_default(),
+                                    time_to_resolve: pending_suspense_boundary.pending_since.elapsed(),
+                                };
+                                if timing.time_to_resolve > threshold {
+                                    tracing::warn!(
+                                        "Suspense boundary {} waiting on {} took {:.1}ms to resolve which is over the {:.1}ms threshold",
+                                        timing.mount,
+                                        timing.components,
+                                        timing.time_to_resolve.as_secs_f64() * 1000.0,
+                                        threshold.as_secs_f64() * 1000.0,
+                                    );
+                                }
+                                suspense_boundary_timings.push(timing);
+                            }
+                            resolved_chunk.clear();
+                            // After we replace the placeholder in the dom with javascript, we need to send down the resolved data so that the client can hydrate the node
+                            let render_suspense = |into: &mut String| {
+                                renderer.reset_hydration();
+                                renderer.render_scope(into, &virtual_dom, scope)
+                            };
+                            let resolved_data = Self::serialize_server_data(&cfg, &virtual_dom, scope);
+                            hydration_bytes += resolved_data.data.len();
+                            Self::check_hydration_budget(
+                                cfg.hydration_boundary_budget,
+                                resolved_data.data.len(),
+                                || {
+                                    format!(
+                                        "The hydration data for suspense boundary {} in \"{route}\"",
+                                        pending_suspense_boundary.mount
+                                    )
+                                },
+                            );
+                            // Send any head changes and evals the boundary made while it resolved along with it
+                            let scripts = BoundaryScripts {
+                                head_patch: document.take_head_patch(),
+                                eval: document.take_eval_script(),
+                            };
+                            stream.replace_placeholder(
+                                pending_suspense_boundary.mount.clone(),
+                                render_suspense,
+                                resolved_data,
+                                scripts,
+                                &mut resolved_chunk,
+                            )
+                        });
+                        if let Err(err) = placeholder {
+                            throw_error!(
+                                IncrementalRendererError::RenderError(err),
+                                pending_suspense_boundary.mount
+                            );
                         }
 
-                        stream.render(resolved_chunk);
+                        stream
+                            .render(&resolved_chunk)
+                            .instrument(boundary_span.clone())
+                            .await;
+                        boundary_span
+                            .record("duration", boundary_start.elapsed().as_secs_f64() * 1000.0);
                         // Freeze the suspense boundary to prevent future reruns of any child nodes of the suspense boundary
                         if let Some(suspense) =
                             SuspenseContext::downcast_suspense_boundary_from_scope(
@@ -387,41 +757,92 @@ impl SsrRendererPool {
                 }
             }
 
+            // Flush any head changes and evals that were not sent with a suspense boundary
+            stream
+                .render_scripts(BoundaryScripts {
+                    head_patch: document.take_head_patch(),
+                    eval: document.take_eval_script(),
+                })
+                .await;
+
+            timings.span.record("hydration_bytes", hydration_bytes);
+            Self::check_hydration_budget(cfg.hydration_page_budget, hydration_bytes, || {
+                format!("The hydration data for \"{route}\"")
+            });
+
+            // In debug mode, summarize the suspense boundary timings in a comment at the end of the body
+            #[cfg(debug_assertions)]
+            if !suspense_boundary_timings.is_empty() {
+                stream
+                    .render(Self::suspense_diagnostics_comment(
+                        &suspense_boundary_timings,
+                    ))
+                    .await;
+            }
+
             // After suspense is done, we render the html after the body
             let mut post_streaming = String::new();
 
-            if let Err(err) = Self::render_after_body(&cfg, &mut post_streaming) {
+            if let Err(err) = Self::render_after_body(&cfg, &mut post_streaming, nonce.as_deref()) {
                 throw_error!(err);
             }
 
-            // If incremental rendering is enabled, add the new render to the cache without the streaming bits
+            // If incremental rendering is enabled, add the new render to the cache without the streaming bits.
+            // The streamed document is already complete, so a failure here only skips caching the route
             if let Some(incremental) = &self.incremental_cache {
                 let mut cached_render = String::new();
-                if let Err(err) = Self::render_head(&cfg, &mut cached_render, &virtual_dom) {
-                    throw_error!(err);
-                }
-                renderer.reset_hydration();
-                if let Err(err) = renderer.render_to(&mut cached_render, &virtual_dom) {
-                    throw_error!(IncrementalRendererError::RenderError(err));
-                }
-                if let Err(err) = Self::render_after_main(&cfg, &mut cached_render, &virtual_dom) {
-                    throw_error!(err);
-                }
-                cached_render.push_str(&post_streaming);
-
-                if let Ok(mut incremental) = incremental.write() {
-                    let _ = incremental.cache(route, cached_render);
+                document.replay_evals();
+                let rendered = Self::render_head(&cfg, &mut cached_render, &virtual_dom)
+                    .and_then(|_| {
+                        renderer.reset_hydration();
+                        renderer
+                            .render_to(&mut cached_render, &virtual_dom)
+                            .map_err(IncrementalRendererError::RenderError)
+                    })
+                    .and_then(|_| Self::render_after_reader(&cfg, &mut cached_render, &virtual_dom))
+                    .map(|_| ());
+
+                match rendered {
+                    Ok(()) => {
+                        cached_render.push_str(&post_streaming);
+                        // Each request gets a new nonce, so the cached route stores a placeholder instead
+                        if let Some(nonce) = &nonce {
+                            cached_render = cached_render.replace(nonce, CSP_NONCE_PLACEHOLDER);
+                        }
+                        if let Ok(mut incremental) = incremental.write() {
+                            let _ = incremental.cache_with_preload_links(
+                                route.clone(),
+                                cached_render,
+                                document.preload_links(),
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            "Failed to render route \"{route}\" for the incremental cache: {err}"
+                        );
+                    }
                 }
             }
 
-            stream.render(post_streaming);
+            stream.render(post_streaming).await;
+            stream.flush().await;
+            timings.record("total", render_start);
+
+            let dropped_chunks = stream.dropped_chunks();
+            if dropped_chunks > 0 {
+                timings.span.record("dropped_chunks", dropped_chunks);
+                tracing::warn!(
+                    "{dropped_chunks} chunks of \"{route}\" were dropped because the client disconnected"
+                );
+            }
 
             renderer.reset_render_components();
             myself.renderers.write().unwrap().push(renderer);
         };
 
         // Spawn the render future onto the local pool
-        let join_handle = rt.spawn_pinned(create_render_future);
+        let join_handle = spawn_render(rt, move || create_render_future().instrument(render_span));
 
         // Wait for the initial result which determines the status code
         let (status, headers) = initial_result_rx
@@ -439,6 +860,197 @@ impl SsrRendererPool {
         ))
     }
 
+    /// Create the status and headers of the response. The status and headers the app set on the FullstackContext are
+    /// extended with the headers from the config
+    fn response_head(
+        cfg: &ServeConfig,
+        streaming_context: &FullstackContext,
+        document: &ServerDocument,
+        nonce: Option<&str>,
+        timings: &RenderTimings,
+    ) -> (HttpError, HeaderMap) {
+        // Check the FullstackContext in case the user set the statuscode manually or via a layout.
+        let http_status = streaming_context.current_http_status();
+        let mut headers = streaming_context
+            .take_response_headers()
+            .unwrap_or_default();
+
+        cfg.write_content_security_policy(&mut headers, nonce);
+        cfg.write_preload_headers(&mut headers, &document.preload_links());
+
+        // Only the phases before the response headers are sent can be included in the `Server-Timing` header.
+        // Later phases are recorded on the render span
+        if cfg.server_timing {
+            timings.write(&mut headers);
+        }
+
+        (http_status, headers)
+    }
+
+    /// Check the root error boundary for an error and map it to the status code of the response
+    fn root_error(cfg: &ServeConfig, virtual_dom: &VirtualDom) -> Option<SSRError> {
+        let error = virtual_dom.in_scope(ScopeId::ROOT_ERROR_BOUNDARY, || {
+            consume_context::<ErrorContext>().error()
+        })?;
+
+        let mut status_code = None;
+        let mut out_message = None;
+
+        // If the errors include an `HttpError` or `StatusCode` or `ServerFnError`, we need
+        // to try and return the appropriate status code
+        if let Some(error) = error.downcast_ref::<HttpError>() {
+            status_code = Some(error.status);
+            out_message = error.message.clone();
+        }
+
+        if let Some(error) = error.downcast_ref::<StatusCode>() {
+            status_code = Some(*error);
+        }
+
+        // The user is allowed to return anything that impls `From<ServerFnError>`. Those error
+        // types are mapped to a status code with the mappings registered in the config
+        for map_error_status in &cfg.error_status_mappers {
+            if let Some(error) = map_error_status(&error) {
+                status_code = Some(error.status);
+                out_message = error.message;
+            }
+        }
+
+        if let Some(ServerFnError::ServerError { message, code, .. }) = error.downcast_ref() {
+            status_code = Some(
+                (*code)
+                    .try_into()
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            );
+
+            out_message = Some(message.clone());
+        }
+
+        // If there was an error while routing, return the error with a 404 status
+        // Return a routing error if any of the errors were a routing error
+        if let Some(routing_error) = error.downcast_ref::<ParseRouteError>().cloned() {
+            status_code = Some(StatusCode::NOT_FOUND);
+            out_message = Some(routing_error.to_string());
+        }
+
+        // If we captured anything that produces a status code, we should return that status code.
+        if let Some(status_code) = status_code {
+            return Some(SSRError::HttpError {
+                status: status_code,
+                message: out_message,
+            });
+        }
+
+        Some(SSRError::Incremental(IncrementalRendererError::Other(
+            error,
+        )))
+    }
+
+    /// Render the html and hydration data for part of the app without the `index.html` shell. The hydration data is
+    /// sent in a data block after the html, followed by a script with the javascript evaluated during the render.
+    fn render_fragment(
+        cfg: &ServeConfig,
+        renderer: &mut Renderer,
+        virtual_dom: &VirtualDom,
+        document: &ServerDocument,
+        nonce: Option<&str>,
+        target: &FragmentTarget,
+    ) -> Result<String, SSRError> {
+        let scope = match target {
+            FragmentTarget::Root => ScopeId::ROOT,
+            FragmentTarget::Boundary(name) => {
+                Self::find_fragment_boundary(virtual_dom, ScopeId::ROOT, name).ok_or_else(|| {
+                    SSRError::HttpError {
+                        status: StatusCode::NOT_FOUND,
+                        message: Some(format!(
+                            "No fragment boundary named \"{name}\" was rendered"
+                        )),
+                    }
+                })?
+            }
+        };
+
+        let mut fragment = String::new();
+        renderer.reset_hydration();
+        renderer
+            .render_scope(&mut fragment, virtual_dom, scope)
+            .map_err(|err| SSRError::Incremental(IncrementalRendererError::RenderError(err)))?;
+
+        let resolved_data = Self::serialize_server_data(cfg, virtual_dom, scope);
+        let encoding = if cfg.compress_hydration_data {
+            r#" data-encoding="deflate-raw""#
+        } else {
+            ""
+        };
+        _ = write!(
+            fragment,
+            r#"<script type="application/octet-stream" data-dx-hydration{encoding}>{}</script>"#,
+            resolved_data.data
+        );
+        // Fragments have no shell to send the evals with later, so they are sent at the end of the fragment
+        if let Some(eval) = document.take_eval_script() {
+            _ = write!(fragment, "<script{}>{eval}</script>", NonceAttribute(nonce));
+        }
+
+        Ok(fragment)
+    }
+
+    /// Render the hydration data for the whole route as json. The data is the base64 encoded payload the client
+    /// normally reads from `initial_dioxus_hydration_data`, in the order the client runs the server futures.
+    fn render_route_data(cfg: &ServeConfig, virtual_dom: &VirtualDom) -> String {
+        let resolved_data = Self::serialize_server_data(cfg, virtual_dom, ScopeId::ROOT);
+        let encoding = if cfg.compress_hydration_data {
+            r#""deflate-raw""#
+        } else {
+            "null"
+        };
+
+        let mut json = format!(r#"{{"data":"{}","encoding":{encoding}"#, resolved_data.data);
+        // In debug mode, we also send down the type names and locations of the serialized data
+        #[cfg(debug_assertions)]
+        {
+            _ = write!(
+                json,
+                r#","debug_types":{},"debug_locations":{}"#,
+                resolved_data.debug_types, resolved_data.debug_locations
+            );
+        }
+        json.push('}');
+        json
+    }
+
+    /// Find the first scope in depth first order that called `use_fragment_boundary` with the name
+    fn find_fragment_boundary(vdom: &VirtualDom, scope: ScopeId, name: &str) -> Option<ScopeId> {
+        let boundary = vdom.in_scope(scope, has_context::<FragmentBoundary>);
+        if boundary.is_some_and(|boundary| boundary.0 == name) {
+            return Some(scope);
+        }
+
+        let node = vdom.get_scope(scope)?.try_root_node()?;
+        Self::find_fragment_boundary_in_vnode(vdom, node, name)
+    }
+
+    fn find_fragment_boundary_in_vnode(
+        vdom: &VirtualDom,
+        vnode: &VNode,
+        name: &str,
+    ) -> Option<ScopeId> {
+        vnode
+            .dynamic_nodes
+            .iter()
+            .enumerate()
+            .find_map(|(index, node)| match node {
+                DynamicNode::Component(component) => {
+                    let scope = component.mounted_scope_id(index, vnode, vdom)?;
+                    Self::find_fragment_boundary(vdom, scope, name)
+                }
+                DynamicNode::Fragment(nodes) => nodes
+                    .iter()
+                    .find_map(|node| Self::find_fragment_boundary_in_vnode(vdom, node, name)),
+                _ => None,
+            })
+    }
+
     fn pre_renderer() -> Renderer {
         let mut renderer = Renderer::default();
         renderer.pre_render = true;
@@ -452,6 +1064,7 @@ impl SsrRendererPool {
     fn streaming_render_component_callback(
         stream: Arc<StreamingRenderer<IncrementalRendererError>>,
         scope_to_mount_mapping: Arc<RwLock<HashMap<ScopeId, PendingSuspenseBoundary>>>,
+        suspense_diagnostics: bool,
     ) -> impl Fn(&mut Renderer, &mut dyn Write, &VirtualDom, ScopeId) -> std::fmt::Result
            + Send
            + Sync
@@ -489,6 +1102,9 @@ impl SsrRendererPool {
                     PendingSuspenseBoundary {
                         mount,
                         children: vec![],
+                        pending_since: Instant::now(),
+                        components: suspense_diagnostics
+                            .then(|| Self::suspended_component_names(vdom, scope)),
                     },
                 );
                 // Add the scope to the list of children of the parent suspense boundary
@@ -513,6 +1129,45 @@ impl SsrRendererPool {
         }
     }
 
+    /// Get the names of the components a suspense boundary renders once it resolves for diagnostics
+    fn suspended_component_names(vdom: &VirtualDom, scope: ScopeId) -> String {
+        let names = SuspenseContext::downcast_suspense_boundary_from_scope(&vdom.runtime(), scope)
+            .and_then(|suspense| suspense.suspended_nodes())
+            .map(|node| {
+                node.dynamic_nodes
+                    .iter()
+                    .filter_map(|node| match node {
+                        DynamicNode::Component(component) => Some(component.name),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if names.is_empty() {
+            "SuspenseBoundary".to_string()
+        } else {
+            names.join(", ")
+        }
+    }
+
+    /// Summarize the time each suspense boundary took to resolve in an html comment
+    #[cfg(debug_assertions)]
+    fn suspense_diagnostics_comment(timings: &[SuspenseBoundaryTiming]) -> String {
+        let mut comment = String::from("<!-- suspense boundary timings:");
+        for timing in timings {
+            _ = write!(
+                comment,
+                "\n  ds-{} ({}): {:.1}ms",
+                timing.mount,
+                timing.components,
+                timing.time_to_resolve.as_secs_f64() * 1000.0
+            );
+        }
+        comment.push_str("\n-->");
+        comment
+    }
+
     /// Start capturing errors at a suspense boundary. If the parent suspense boundary is frozen, we need to capture the errors in the suspense boundary
     /// and send them to the client to continue bubbling up
     fn start_capturing_errors(suspense_scope: ScopeId) {
@@ -523,13 +1178,64 @@ impl SsrRendererPool {
         });
     }
 
-    fn serialize_server_data(virtual_dom: &VirtualDom, scope: ScopeId) -> SerializedHydrationData {
+    /// Serialize an error that happened after streaming started so it can be raised in the nearest error boundary
+    /// on the client. Internal error details are only sent in debug builds.
+    #[track_caller]
+    fn serialize_error(
+        cfg: &ServeConfig,
+        error: IncrementalRendererError,
+    ) -> SerializedHydrationData {
+        let error = if cfg!(debug_assertions) {
+            CapturedError::from(error)
+        } else {
+            CapturedError::from_display("Internal Server Error")
+        };
+        let data = HydrationContext::default();
+        data.error_entry()
+            .insert(&Some(error), std::panic::Location::caller());
+        Self::encode_hydration_data(cfg, data.serialized())
+    }
+
+    fn serialize_server_data(
+        cfg: &ServeConfig,
+        virtual_dom: &VirtualDom,
+        scope: ScopeId,
+    ) -> SerializedHydrationData {
         // After we replace the placeholder in the dom with javascript, we need to send down the resolved data so that the client can hydrate the node
         // Extract any data we serialized for hydration (from server futures)
         let html_data = Self::extract_from_suspense_boundary(virtual_dom, scope);
 
         // serialize the server state into a base64 string
-        html_data.serialized()
+        Self::encode_hydration_data(cfg, html_data.serialized())
+    }
+
+    /// Compress the serialized data with deflate before it is base64 encoded if hydration data compression is enabled.
+    /// The client is told to decode the data with the `window.dioxus_hydration_encoding` flag in the head.
+    fn encode_hydration_data(
+        cfg: &ServeConfig,
+        mut serialized: SerializedHydrationData,
+    ) -> SerializedHydrationData {
+        use base64::Engine;
+
+        if cfg.compress_hydration_data {
+            let engine = base64::engine::general_purpose::STANDARD;
+            match engine.decode(&serialized.data) {
+                Ok(data) => serialized.data = engine.encode(deflate_raw(&data)),
+                Err(err) => tracing::error!("Failed to compress hydration data: {err}"),
+            }
+        }
+
+        serialized
+    }
+
+    /// Log a warning if the size of some hydration data is over the budget
+    fn check_hydration_budget(budget: Option<usize>, size: usize, data: impl FnOnce() -> String) {
+        if let Some(budget) = budget.filter(|budget| size > *budget) {
+            tracing::warn!(
+                "{} is {size} bytes which is over the hydration data budget of {budget} bytes. Consider moving large server future results into a separate request.",
+                data()
+            );
+        }
     }
 
     /// Walks through the suspense boundary in a depth first order and extracts the data from the context API.
@@ -681,16 +1387,19 @@ impl SsrRendererPool {
             document.and_then(|document| document.title())
         };
 
-        to.write_str(&cfg.index.head_before_title)?;
+        let document =
+            virtual_dom.in_scope(ScopeId::ROOT, try_consume_context::<Rc<ServerDocument>>);
+        let nonce = document.as_ref().and_then(|document| document.nonce());
+        let nonce = nonce.as_deref();
+
+        cfg.index.head_before_title.write_with_nonce(to, nonce)?;
         if let Some(title) = title {
             to.write_str(&title)?;
         } else {
             to.write_str(&cfg.index.title)?;
         }
-        to.write_str(&cfg.index.head_after_title)?;
+        cfg.index.head_after_title.write_with_nonce(to, nonce)?;
 
-        let document =
-            virtual_dom.in_scope(ScopeId::ROOT, try_consume_context::<Rc<ServerDocument>>);
         if let Some(document) = document {
             // Collect any head content from the document provider and inject that into the head
             document.render(to)?;
@@ -699,7 +1408,50 @@ impl SsrRendererPool {
             document.start_streaming();
         }
 
-        Self::render_before_body(cfg, to)?;
+        Self::render_before_body(cfg, to, nonce)?;
+
+        Ok(())
+    }
+
+    /// Render the static part of the head that doesn't depend on the app. This is sent before the app renders when
+    /// the head is flushed early.
+    fn render_early_head<R: std::fmt::Write>(
+        cfg: &ServeConfig,
+        to: &mut R,
+        nonce: Option<&str>,
+    ) -> Result<(), IncrementalRendererError> {
+        cfg.index.head_before_title.write_with_nonce(to, nonce)?;
+        to.write_str(&cfg.index.title)?;
+        cfg.index.head_after_title.write_with_nonce(to, nonce)?;
+        Self::render_bootstrap_scripts(cfg, to, nonce)
+    }
+
+    /// Render the rest of the head after it was flushed early. This includes the head elements from the app and a
+    /// script that sets the title if the app changed it.
+    fn render_late_head<R: std::fmt::Write>(
+        cfg: &ServeConfig,
+        to: &mut R,
+        virtual_dom: &VirtualDom,
+    ) -> Result<(), IncrementalRendererError> {
+        let document =
+            virtual_dom.in_scope(ScopeId::ROOT, try_consume_context::<Rc<ServerDocument>>);
+        let nonce = document.as_ref().and_then(|document| document.nonce());
+
+        if let Some(document) = document {
+            document.render(to)?;
+            if let Some(title) = document.title_script() {
+                write!(
+                    to,
+                    "<script{}>{title}</script>",
+                    NonceAttribute(nonce.as_deref())
+                )?;
+            }
+            document.start_streaming();
+        }
+
+        cfg.index
+            .close_head
+            .write_with_nonce(to, nonce.as_deref())?;
 
         Ok(())
     }
@@ -708,60 +1460,733 @@ impl SsrRendererPool {
     fn render_before_body<R: std::fmt::Write>(
         cfg: &ServeConfig,
         to: &mut R,
+        nonce: Option<&str>,
     ) -> Result<(), IncrementalRendererError> {
-        to.write_str(&cfg.index.close_head)?;
+        cfg.index.close_head.write_with_nonce(to, nonce)?;
+        Self::render_bootstrap_scripts(cfg, to, nonce)
+    }
 
+    /// Render the scripts that set up streaming and hydration in the client
+    fn render_bootstrap_scripts<R: std::fmt::Write>(
+        cfg: &ServeConfig,
+        to: &mut R,
+        nonce: Option<&str>,
+    ) -> Result<(), IncrementalRendererError> {
         // // #[cfg(feature = "document")]
         // {
         use dioxus_interpreter_js::INITIALIZE_STREAMING_JS;
-        write!(to, "<script>{INITIALIZE_STREAMING_JS}</script>")?;
+        write!(
+            to,
+            "<script{}>{INITIALIZE_STREAMING_JS}</script>",
+            NonceAttribute(nonce)
+        )?;
+        if cfg.compress_hydration_data {
+            write!(
+                to,
+                r#"<script{}>window.dioxus_hydration_encoding="deflate-raw";</script>"#,
+                NonceAttribute(nonce)
+            )?;
+        }
+        if cfg.hydration_payload == HydrationPayload::DataBlock {
+            write!(
+                to,
+                "<script{}>{HYDRATION_DATA_BLOCK_READER}</script>",
+                NonceAttribute(nonce)
+            )?;
+        }
         // }
 
         Ok(())
     }
 
-    /// Render all content after the main element of the page.
-    pub fn render_after_main<R: std::fmt::Write>(
+    /// Render all content after the main element of the page. Returns the size of the hydration data for the root of the page.
+    pub fn render_after_reader<R: std::fmt::Write>(
         cfg: &ServeConfig,
         to: &mut R,
         virtual_dom: &VirtualDom,
-    ) -> Result<(), IncrementalRendererError> {
+    ) -> Result<usize, IncrementalRendererError> {
+        let document =
+            virtual_dom.in_scope(ScopeId::ROOT, try_consume_context::<Rc<ServerDocument>>);
+        let nonce = document.as_ref().and_then(|document| document.nonce());
+
         // Collect the initial server data from the root node. For most apps, no use_server_futures will be resolved initially, so this will be full on `None`s.
         // Sending down those Nones are still important to tell the client not to run the use_server_futures that are already running on the backend
-        let resolved_data = SsrRendererPool::serialize_server_data(virtual_dom, ScopeId::ROOT);
+        let resolved_data = SsrRendererPool::serialize_server_data(cfg, virtual_dom, ScopeId::ROOT);
+        let hydration_size = resolved_data.data.len();
         // We always send down the data required to hydrate components on the client
         let raw_data = resolved_data.data;
-        write!(
-            to,
-            r#"<script>window.initial_dioxus_hydration_data="{raw_data}";"#,
-        )?;
-        #[cfg(debug_assertions)]
-        {
-            // In debug mode, we also send down the type names and locations of the serialized data
-            let debug_types = &resolved_data.debug_types;
-            let debug_locations = &resolved_data.debug_locations;
-            write!(
-                to,
-                r#"window.initial_dioxus_hydration_debug_types={debug_types};"#,
-            )?;
+        match cfg.hydration_payload {
+            HydrationPayload::Script => {
+                write!(
+                    to,
+                    r#"<script{}>window.initial_dioxus_hydration_data="{raw_data}";"#,
+                    NonceAttribute(nonce.as_deref()),
+                )?;
+                #[cfg(debug_assertions)]
+                {
+                    // In debug mode, we also send down the type names and locations of the serialized data
+                    let debug_types = &resolved_data.debug_types;
+                    let debug_locations = &resolved_data.debug_locations;
+                    write!(
+                        to,
+                        r#"window.initial_dioxus_hydration_debug_types={debug_types};"#,
+                    )?;
+                    write!(
+                        to,
+                        r#"window.initial_dioxus_hydration_debug_locations={debug_locations};"#,
+                    )?;
+                }
+                write!(to, r#"</script>"#,)?;
+            }
+            HydrationPayload::DataBlock => {
+                // Data blocks are never executed, so they don't need a nonce
+                write!(
+                    to,
+                    r#"<script type="application/octet-stream" id="dx-hydration">{raw_data}</script>"#,
+                )?;
+                #[cfg(debug_assertions)]
+                {
+                    // In debug mode, we also send down the type names and locations of the serialized data
+                    let debug_types = &resolved_data.debug_types;
+                    let debug_locations = &resolved_data.debug_locations;
+                    write!(
+                        to,
+                        r#"<script type="application/json" id="dx-hydration-debug">{{"types":{debug_types},"locations":{debug_locations}}}</script>"#,
+                    )?;
+                }
+            }
+        }
+        // Run any javascript evaluated during the initial render once the main element is in the dom
+        if let Some(eval) = document.and_then(|document| document.take_eval_script()) {
             write!(
                 to,
-                r#"window.initial_dioxus_hydration_debug_locations={debug_locations};"#,
+                "<script{}>{eval}</script>",
+                NonceAttribute(nonce.as_deref())
             )?;
         }
-        write!(to, r#"</script>"#,)?;
-        to.write_str(&cfg.index.post_main)?;
+        cfg.index.post_main.write_with_nonce(to, nonce.as_deref())?;
 
-        Ok(())
+        Ok(hydration_size)
     }
 
     /// Render all content after the body of the page.
     pub fn render_after_body<R: std::fmt::Write>(
         cfg: &ServeConfig,
         to: &mut R,
+        nonce: Option<&str>,
     ) -> Result<(), IncrementalRendererError> {
-        to.write_str(&cfg.index.after_closing_body_tag)?;
+        cfg.index
+            .after_closing_body_tag
+            .write_with_nonce(to, nonce)?;
 
         Ok(())
     }
+
+    /// Render the configured error page for a failed request inside the `index.html` shell. Returns `None` if
+    /// no error page is registered for the status class.
+    pub(crate) fn render_error_page(
+        cfg: &ServeConfig,
+        props: ErrorPageProps,
+        nonce: Option<&str>,
+    ) -> Option<Result<String, IncrementalRendererError>> {
+        let page = cfg.error_pages.get(&StatusClass::of(props.status)?)?;
+
+        let mut html = String::new();
+        let result = (|| {
+            render_shell_start(cfg, &mut html, nonce)?;
+            Self::render_error_page_body(page, props, &mut html)?;
+            cfg.index.post_main.write_with_nonce(&mut html, nonce)?;
+            Self::render_after_body(cfg, &mut html, nonce)
+        })();
+
+        Some(result.map(|_| html))
+    }
+
+    /// Render the error page into the body of a document whose head was already streamed. If no error page is
+    /// registered for the status class, the main element only contains the error message.
+    fn render_streamed_error_page(
+        cfg: &ServeConfig,
+        to: &mut String,
+        nonce: Option<&str>,
+        props: ErrorPageProps,
+    ) -> Result<(), IncrementalRendererError> {
+        cfg.index.close_head.write_with_nonce(to, nonce)?;
+        match StatusClass::of(props.status).and_then(|class| cfg.error_pages.get(&class)) {
+            Some(page) => Self::render_error_page_body(page, props, to)?,
+            None => to.push_str(&escape_html(&props.fallback_message())),
+        }
+        cfg.index.post_main.write_with_nonce(to, nonce)?;
+        Self::render_after_body(cfg, to, nonce)
+    }
+
+    /// Render the content of an error page that goes inside the main element
+    fn render_error_page_body(
+        page: &ErrorPage,
+        props: ErrorPageProps,
+        to: &mut String,
+    ) -> Result<(), IncrementalRendererError> {
+        match page {
+            ErrorPage::Component(component) => {
+                let mut virtual_dom = VirtualDom::new_with_props(*component, props);
+                virtual_dom.rebuild_in_place();
+                Renderer::new().render_to(to, &virtual_dom)?;
+            }
+            ErrorPage::Template(template) => render_error_template(template, &props, to),
+        }
+        Ok(())
+    }
+}
+
+/// Replace the placeholders in an error page template. The template is substituted in a single pass, so placeholders
+/// inside the message or details are left as they are.
+fn render_error_template(template: &str, props: &ErrorPageProps, to: &mut String) {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        to.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = rest.find('}').map_or("", |end| &rest[..=end]);
+        let value = match placeholder {
+            "{status}" => props.status.as_str().to_string(),
+            "{reason}" => props
+                .status
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+            "{message}" => escape_html(props.message.as_deref().unwrap_or_default()),
+            "{details}" => escape_html(props.details.as_deref().unwrap_or_default()),
+            _ => {
+                to.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        to.push_str(&value);
+        rest = &rest[placeholder.len()..];
+    }
+    to.push_str(rest);
+}
+
+/// Write the `index.html` shell up to the opening of the main element
+fn render_shell_start(cfg: &ServeConfig, to: &mut String, nonce: Option<&str>) -> std::fmt::Result {
+    cfg.index.head_before_title.write_with_nonce(to, nonce)?;
+    to.write_str(&cfg.index.title)?;
+    cfg.index.head_after_title.write_with_nonce(to, nonce)?;
+    cfg.index.close_head.write_with_nonce(to, nonce)
+}
+
+/// Escape text that is inserted into an html template
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_error_template, SSRError, SsrRendererPool, HYDRATION_DATA_BLOCK_READER};
+    use crate::streaming::StreamingRenderer;
+    use crate::{use_fragment_boundary, IncrementalRendererConfig, IncrementalRendererError};
+    use crate::{
+        ErrorPage, ErrorPageProps, FullstackState, HttpStatusError, HydrationPayload, IndexHtml,
+        ServeConfig, StatusClass,
+    };
+    use axum::{body::Body, extract::State, response::IntoResponse};
+    use dioxus_core::{spawn, suspend, use_hook, Element, VNode};
+    use dioxus_fullstack_core::HttpError;
+    use dioxus_hooks::use_signal;
+    use dioxus_signals::WritableExt;
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Suspends on the first render and fails once the suspended task finishes
+    fn fails_after_suspense() -> Element {
+        let mut ready = use_signal(|| false);
+        if ready() {
+            return Err(anyhow::anyhow!("database offline").into());
+        }
+        let task = use_hook(|| {
+            spawn(async move {
+                tokio::task::yield_now().await;
+                ready.set(true);
+            })
+        });
+        suspend(task)
+    }
+
+    async fn render(cfg: ServeConfig, app: fn() -> Element) -> (http::StatusCode, String) {
+        render_uri(FullstackState::new(cfg, app), "/").await
+    }
+
+    async fn render_uri(state: FullstackState, uri: &str) -> (http::StatusCode, String) {
+        let request = http::Request::get(uri).body(Body::empty()).unwrap();
+        let response = FullstackState::render_handler(State(state), request)
+            .await
+            .into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn errors_after_the_early_head_flush_render_the_error_page_in_the_body() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only())
+            .enable_out_of_order_streaming()
+            .early_head_flush(true)
+            .error_page(
+                StatusClass::ServerError,
+                ErrorPage::Template("<h1>{status} {reason}</h1>".to_string()),
+            );
+
+        let (status, body) = render(cfg, fails_after_suspense).await;
+
+        // The status was sent with the head before the error was found
+        assert_eq!(status, http::StatusCode::OK);
+        let main = body.split(r#"<div id="main">"#).nth(1).unwrap();
+        assert!(main.starts_with("<h1>500 Internal Server Error</h1></div>"));
+        assert!(body.trim_end().ends_with("</html>"));
+    }
+
+    #[tokio::test]
+    async fn errors_after_the_early_head_flush_without_an_error_page_render_the_message() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only())
+            .enable_out_of_order_streaming()
+            .early_head_flush(true);
+
+        let (_, body) = render(cfg, fails_after_suspense).await;
+
+        let main = body.split(r#"<div id="main">"#).nth(1).unwrap();
+        let (content, _) = main.split_once("</div>").unwrap();
+        if cfg!(debug_assertions) {
+            assert!(content.contains("database offline"));
+        } else {
+            assert_eq!(content, "Internal Server Error");
+        }
+    }
+
+    fn empty() -> Element {
+        VNode::empty()
+    }
+
+    #[tokio::test]
+    async fn errors_in_a_streamed_boundary_are_sent_as_its_resolved_data() {
+        use base64::Engine;
+        use futures_util::StreamExt;
+
+        let (tx, rx) = futures_channel::mpsc::channel(16);
+        let stream: StreamingRenderer<IncrementalRendererError> = StreamingRenderer::new(
+            "<head></head>",
+            tx,
+            Some("abc".to_string()),
+            Default::default(),
+        );
+        let mut initial_frame = String::new();
+        let mount = stream
+            .render_placeholder(|to| write!(to, "loading"), &mut initial_frame)
+            .unwrap();
+        stream.render(initial_frame).await;
+        stream.flush().await;
+
+        // The boundary fails to render after the first frame was flushed
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only());
+        let error = SsrRendererPool::serialize_error(
+            &cfg,
+            IncrementalRendererError::RenderError(std::fmt::Error),
+        );
+        let data = error.data.clone();
+        #[cfg(debug_assertions)]
+        let debug = format!(", {}, {}", error.debug_types, error.debug_locations);
+        #[cfg(not(debug_assertions))]
+        let debug = "";
+        stream
+            .close_with_error(Some((mount, error)), "</body></html>")
+            .await;
+
+        let frames = rx
+            .map(|frame| String::from_utf8(frame.unwrap().to_vec()).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(frames.len(), 3, "{frames:?}");
+        assert_eq!(frames[..2], ["<head></head>", "loading"]);
+        assert_eq!(
+            frames[2],
+            format!(
+                r#"<div id="ds-0-r" hidden></div><script nonce="abc">window.dx_hydrate([0], "{data}"{debug})</script></body></html>"#
+            )
+        );
+
+        // The client reads the error of a boundary from the first entry of its resolved data
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(&data)
+            .unwrap();
+        let error = dioxus_fullstack_core::HydrationContext::from_serialized(&data, None, None)
+            .error_entry()
+            .get()
+            .unwrap()
+            .unwrap();
+        let message = if cfg!(debug_assertions) {
+            "RenderError: an error occurred when formatting an argument"
+        } else {
+            "Internal Server Error"
+        };
+        assert_eq!(error.to_string(), message);
+    }
+
+    #[derive(Debug)]
+    struct PostNotFound;
+
+    impl std::fmt::Display for PostNotFound {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "post not found")
+        }
+    }
+
+    impl HttpStatusError for PostNotFound {
+        fn status(&self) -> http::StatusCode {
+            http::StatusCode::NOT_FOUND
+        }
+    }
+
+    #[derive(Debug)]
+    struct NotAllowed;
+
+    impl std::fmt::Display for NotAllowed {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "not allowed")
+        }
+    }
+
+    fn post_not_found() -> Element {
+        Err(anyhow::Error::msg(PostNotFound).into())
+    }
+
+    fn not_allowed() -> Element {
+        Err(anyhow::Error::msg(NotAllowed).into())
+    }
+
+    #[tokio::test]
+    async fn registered_errors_are_rendered_with_their_status() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only())
+            .error_status::<PostNotFound>()
+            .map_error_status(|_: &NotAllowed| HttpError {
+                status: http::StatusCode::FORBIDDEN,
+                message: Some("ask an admin".to_string()),
+            })
+            .error_page(
+                StatusClass::ClientError,
+                ErrorPage::Template("<h1>{status}: {message}</h1>".to_string()),
+            );
+
+        let (status, body) = render(cfg.clone(), post_not_found).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+        assert!(body.contains("<h1>404: post not found</h1>"), "{body}");
+
+        let (status, body) = render(cfg, not_allowed).await;
+        assert_eq!(status, http::StatusCode::FORBIDDEN);
+        assert!(body.contains("<h1>403: ask an admin</h1>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn unregistered_errors_are_rendered_as_internal_errors() {
+        let (status, _) = render(
+            ServeConfig::with_index_html(IndexHtml::ssr_only()),
+            post_not_found,
+        )
+        .await;
+        assert_eq!(status, http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn data_block_payload_moves_the_hydration_data_out_of_inline_scripts() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only())
+            .hydration_payload(HydrationPayload::DataBlock);
+
+        let (status, body) = render(cfg, empty).await;
+
+        assert_eq!(status, http::StatusCode::OK);
+        let reader = format!("<script>{HYDRATION_DATA_BLOCK_READER}</script>");
+        let (_, after_reader) = body.split_once(&reader).unwrap();
+        assert!(
+            after_reader.contains(r#"<script type="application/octet-stream" id="dx-hydration">"#)
+        );
+        assert_eq!(
+            after_reader.contains(r#"<script type="application/json" id="dx-hydration-debug">"#),
+            cfg!(debug_assertions)
+        );
+        assert!(!body.contains("window.initial_dioxus_hydration_data="));
+    }
+
+    #[tokio::test]
+    async fn script_payload_assigns_the_hydration_data_in_an_inline_script() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only());
+
+        let (_, body) = render(cfg, empty).await;
+
+        assert!(body.contains(r#"<script>window.initial_dioxus_hydration_data=""#));
+        assert!(!body.contains(HYDRATION_DATA_BLOCK_READER));
+        assert!(!body.contains(r#"id="dx-hydration""#));
+    }
+
+    fn template(template: &str, props: &ErrorPageProps) -> String {
+        let mut html = String::new();
+        render_error_template(template, props, &mut html);
+        html
+    }
+
+    #[test]
+    fn error_templates_replace_every_placeholder() {
+        let props = ErrorPageProps {
+            status: http::StatusCode::NOT_FOUND,
+            message: Some("No post <1>".to_string()),
+            details: Some("posts & comments".to_string()),
+        };
+        assert_eq!(
+            template(
+                "<h1>{status} {reason}</h1><p>{message}</p><pre>{details}</pre>{status}",
+                &props
+            ),
+            "<h1>404 Not Found</h1><p>No post &lt;1&gt;</p><pre>posts &amp; comments</pre>404"
+        );
+    }
+
+    #[test]
+    fn error_templates_leave_placeholders_in_values_as_they_are() {
+        let props = ErrorPageProps {
+            status: http::StatusCode::BAD_REQUEST,
+            message: Some("{details}".to_string()),
+            details: Some("{status} {message}".to_string()),
+        };
+        assert_eq!(
+            template("{message}|{details}", &props),
+            "{details}|{status} {message}"
+        );
+    }
+
+    #[test]
+    fn error_templates_keep_unknown_braces() {
+        let props = ErrorPageProps {
+            status: http::StatusCode::INTERNAL_SERVER_ERROR,
+            message: None,
+            details: None,
+        };
+        assert_eq!(
+            template(
+                "<style>p { color: red }</style>{{status}}{message}{",
+                &props
+            ),
+            "<style>p { color: red }</style>{500}{"
+        );
+    }
+
+    fn render_error() -> SSRError {
+        SSRError::Incremental(IncrementalRendererError::Other(
+            anyhow::anyhow!("database offline").into(),
+        ))
+    }
+
+    #[test]
+    fn error_details_are_only_included_when_requested() {
+        let props = render_error().error_page_props(true);
+        assert_eq!(props.status, http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(props.message, None);
+        assert!(props.details.unwrap().contains("database offline"));
+
+        let props = render_error().error_page_props(false);
+        assert_eq!(props.details, None);
+        assert_eq!(props.fallback_message(), "Internal Server Error");
+    }
+
+    #[test]
+    fn http_error_messages_are_shown_in_every_build() {
+        let error = || SSRError::HttpError {
+            status: http::StatusCode::NOT_FOUND,
+            message: Some("No post with that id".to_string()),
+        };
+        for include_details in [true, false] {
+            let props = error().error_page_props(include_details);
+            assert_eq!(props.status, http::StatusCode::NOT_FOUND);
+            assert_eq!(props.message.as_deref(), Some("No post with that id"));
+            assert_eq!(props.details, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn error_pages_only_show_details_in_debug_builds() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only()).error_page(
+            StatusClass::ServerError,
+            ErrorPage::Template("<pre>{details}</pre>".to_string()),
+        );
+
+        let (status, body) = render(cfg, fails).await;
+
+        assert_eq!(status, http::StatusCode::INTERNAL_SERVER_ERROR);
+        let (_, details) = body.split_once("<pre>").unwrap();
+        let (details, _) = details.split_once("</pre>").unwrap();
+        if cfg!(debug_assertions) {
+            assert!(details.contains("database offline"));
+        } else {
+            assert_eq!(details, "");
+        }
+    }
+
+    fn fails() -> Element {
+        Err(anyhow::anyhow!("database offline").into())
+    }
+
+    fn post_list() -> Element {
+        use_fragment_boundary("post-list");
+        VNode::empty()
+    }
+
+    fn post_list_with_eval() -> Element {
+        use_fragment_boundary("post-list");
+        use_hook(|| dioxus_document::eval("window.loaded = true;"));
+        VNode::empty()
+    }
+
+    #[tokio::test]
+    async fn fragment_renders_end_with_the_evals() {
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only())
+            .content_security_policy("script-src 'nonce-{nonce}'");
+        let state = FullstackState::new(cfg, post_list_with_eval);
+
+        for uri in ["/?dx-fragment", "/?dx-fragment=post-list"] {
+            let (status, body) = render_uri(state.clone(), uri).await;
+            assert_eq!(status, http::StatusCode::OK);
+            let (_, script) = body.rsplit_once("<script nonce=\"").unwrap();
+            assert!(
+                script.ends_with(
+                    "\">(async function(){window.loaded = true;\n})().catch(console.error);</script>"
+                ),
+                "{body}"
+            );
+        }
+    }
+
+    fn is_document(body: &str) -> bool {
+        body.contains("<html") && body.contains(r#"<div id="main">"#)
+    }
+
+    #[tokio::test]
+    async fn fragment_renders_skip_the_index_html_shell() {
+        let state = FullstackState::new(
+            ServeConfig::with_index_html(IndexHtml::ssr_only()),
+            post_list,
+        );
+
+        let (status, body) = render_uri(state.clone(), "/?dx-fragment").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(!is_document(&body));
+        assert!(body.contains("data-dx-hydration"));
+
+        let (status, body) = render_uri(state.clone(), "/?dx-fragment=post%2Dlist").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(!is_document(&body));
+
+        let (status, _) = render_uri(state.clone(), "/?dx-fragment=comments").await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+
+        // Names that could change the path of the cache entry are rejected before the cache is read
+        for name in ["..%2F..%2Fetc", "post%20list", "a.b"] {
+            let (status, _) = render_uri(state.clone(), &format!("/?dx-fragment={name}")).await;
+            assert_eq!(status, http::StatusCode::BAD_REQUEST);
+        }
+        let request = http::Request::get("/")
+            .header("dx-fragment", "../../static")
+            .body(Body::empty())
+            .unwrap();
+        let response = FullstackState::render_handler(State(state.clone()), request).await;
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+        let (status, body) = render_uri(state, "/").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(is_document(&body));
+    }
+
+    static CACHED_APP_RENDERS: AtomicUsize = AtomicUsize::new(0);
+
+    fn counts_renders() -> Element {
+        use_hook(|| CACHED_APP_RENDERS.fetch_add(1, Ordering::SeqCst));
+        post_list()
+    }
+
+    #[tokio::test]
+    async fn fragments_are_cached_separately_from_the_document() {
+        let static_dir =
+            std::env::temp_dir().join(format!("dioxus-ssr-fragment-isrg-{}", std::process::id()));
+        let cfg = ServeConfig::with_index_html(IndexHtml::ssr_only())
+            .incremental(IncrementalRendererConfig::new().static_dir(&static_dir));
+        let state = FullstackState::new(cfg, counts_renders);
+
+        let document = render_uri(state.clone(), "/posts").await;
+        let fragment = render_uri(state.clone(), "/posts?dx-fragment=post%2Dlist").await;
+        assert!(is_document(&document.1));
+        assert!(!is_document(&fragment.1));
+        assert_eq!(CACHED_APP_RENDERS.load(Ordering::SeqCst), 2);
+
+        // Both renders are served from their own cache entry without rendering the app again
+        assert_eq!(render_uri(state.clone(), "/posts").await, document);
+        assert_eq!(
+            render_uri(state.clone(), "/posts?dx-fragment=post%2Dlist").await,
+            fragment
+        );
+        assert_eq!(CACHED_APP_RENDERS.load(Ordering::SeqCst), 2);
+
+        // Each boundary has its own entry as well
+        let root = render_uri(state, "/posts?dx-fragment").await;
+        assert!(!is_document(&root.1));
+        assert_eq!(CACHED_APP_RENDERS.load(Ordering::SeqCst), 3);
+        _ = std::fs::remove_dir_all(static_dir);
+    }
+
+    fn shell_with_content_after_main() -> IndexHtml {
+        IndexHtml::new(
+            r#"<html><head></head><body><main id="main"></main><footer>Footer</footer><script src="/app.js"></script></body></html>"#,
+            "main",
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn error_pages_keep_the_shell_after_the_main_element() {
+        let cfg = ServeConfig::with_index_html(shell_with_content_after_main())
+            .csp_nonce(true)
+            .error_page(
+                StatusClass::ServerError,
+                ErrorPage::Template("<h1>{status}</h1>".to_string()),
+            );
+
+        let (_, body) = render(cfg, fails).await;
+
+        let (_, main) = body.split_once(r#"<main id="main">"#).unwrap();
+        assert!(main.starts_with(r#"<h1>500</h1></main><footer>Footer</footer><script nonce=""#));
+        assert!(main.contains(r#" src="/app.js"></script></body></html>"#));
+    }
+
+    #[tokio::test]
+    async fn streamed_error_pages_keep_the_shell_after_the_main_element() {
+        let cfg = ServeConfig::with_index_html(shell_with_content_after_main())
+            .enable_out_of_order_streaming()
+            .early_head_flush(true);
+
+        let (_, body) = render(cfg, fails_after_suspense).await;
+
+        let (_, main) = body.split_once(r#"<main id="main">"#).unwrap();
+        let (content, rest) = main.split_once("</main>").unwrap();
+        assert!(!content.is_empty());
+        assert_eq!(
+            rest,
+            r#"<footer>Footer</footer><script src="/app.js"></script></body></html>"#
+        );
+    }
 }
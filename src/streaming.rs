@@ -22,17 +22,34 @@
 //!     <div>Final HTML</div>
 //! </div>
 //! <script>
+//!     // If the boundary changed the head, a script that patches the head
+//! </script>
+//! <script>
 //!     window.dx_hydrate(2, "suspenseboundarydata");
 //! </script>
+//! <script>
+//!     // If the boundary called eval, the evaluated javascript
+//! </script>
 //! ```
 
+use crate::document::NonceAttribute;
+use bytes::{Bytes, BytesMut};
 use dioxus_fullstack_core::SerializedHydrationData;
 use futures_channel::mpsc::Sender;
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 use std::{
     fmt::{Display, Write},
-    sync::{Arc, RwLock},
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
 };
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
 /// Sections are identified by a unique id based on the suspense path. We only track the path of suspense boundaries because the client may render different components than the server.
 #[derive(Clone, Debug, Default)]
@@ -59,33 +76,136 @@ impl Display for MountPath {
     }
 }
 
+/// When the streaming renderer sends the chunks it has buffered to the client
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FlushPolicy {
+    /// Flush once this many bytes are buffered
+    pub(crate) max_bytes: usize,
+    /// Flush once the oldest buffered chunk has waited this long. With a zero window, chunks are only coalesced while
+    /// the renderer has more work ready, so buffering never delays a chunk.
+    pub(crate) max_delay: Duration,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: 16 * 1024,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// Chunks that were rendered but not sent to the client yet
+#[derive(Default)]
+struct PendingChunks {
+    html: BytesMut,
+    since: Option<Instant>,
+}
+
 pub(crate) struct StreamingRenderer<E = std::convert::Infallible> {
-    channel: RwLock<Sender<Result<String, E>>>,
+    channel: RwLock<Sender<Result<Bytes, E>>>,
     current_path: RwLock<MountPath>,
+    nonce: Option<String>,
+    flush_policy: FlushPolicy,
+    pending: Mutex<PendingChunks>,
+    /// The number of chunks that could not be sent because the client disconnected
+    dropped_chunks: AtomicUsize,
 }
 
 impl<E> StreamingRenderer<E> {
-    /// Create a new streaming renderer with the given head that renders into a channel
+    /// Create a new streaming renderer with the given head that renders into a channel. The head is sent right away
+    /// and later chunks are buffered according to the flush policy. The nonce is added to every script the renderer
+    /// creates
     pub(crate) fn new(
         before_body: impl Display,
-        mut render_into: Sender<Result<String, E>>,
+        mut render_into: Sender<Result<Bytes, E>>,
+        nonce: Option<String>,
+        flush_policy: FlushPolicy,
     ) -> Self {
         let start_html = before_body.to_string();
-        _ = render_into.start_send(Ok(start_html));
+        // The channel is new, so it always has space for the first chunk
+        let dropped_chunks = render_into.start_send(Ok(start_html.into())).is_err() as usize;
 
         Self {
             channel: render_into.into(),
             current_path: Default::default(),
+            nonce,
+            flush_policy,
+            pending: Default::default(),
+            dropped_chunks: AtomicUsize::new(dropped_chunks),
+        }
+    }
+
+    /// Render a new chunk of html that will never change. The chunk is buffered with the other pending chunks and
+    /// sent once the buffer is over the byte threshold or the oldest chunk is older than the flush window
+    pub(crate) async fn render(&self, html: impl Display) {
+        let should_flush = {
+            let mut pending = self.pending.lock().unwrap();
+            _ = write!(pending.html, "{html}");
+            let since = *pending.since.get_or_insert_with(Instant::now);
+            let window = self.flush_policy.max_delay;
+            pending.html.len() >= self.flush_policy.max_bytes
+                || (!window.is_zero() && since.elapsed() >= window)
+        };
+        if should_flush {
+            self.flush().await;
+        }
+    }
+
+    /// Send all pending chunks to the client as one frame. This waits until the channel has space for the frame, so a
+    /// slow client slows down the render instead of losing chunks
+    pub(crate) async fn flush(&self) {
+        let html = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.html.is_empty() {
+                return;
+            }
+            pending.since = None;
+            // The frame shares the buffer of the pending chunks. Once the body has sent it, the next frames reuse the
+            // same allocation
+            pending.html.split().freeze()
+        };
+        let sent = std::future::poll_fn(|cx| self.channel.write().unwrap().poll_ready(cx))
+            .await
+            .and_then(|_| self.channel.write().unwrap().start_send(Ok(html)));
+        if sent.is_err() {
+            self.dropped_chunks.fetch_add(1, Ordering::Relaxed);
         }
     }
 
-    /// Render a new chunk of html that will never change
-    pub(crate) fn render(&self, html: impl Display) {
-        _ = self
-            .channel
-            .write()
-            .unwrap()
-            .start_send(Ok(html.to_string()));
+    /// Wait for more work while making sure pending chunks are not held longer than the flush window. With a zero
+    /// window, pending chunks are sent before waiting.
+    pub(crate) async fn flush_while<F: Future>(&self, work: F) -> F::Output {
+        let since = self.pending.lock().unwrap().since;
+        #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+        let Some(since) = since
+        else {
+            return work.await;
+        };
+
+        // There are no timers on wasm32, so pending chunks are always sent before waiting
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let remaining = self.flush_policy.max_delay.saturating_sub(since.elapsed());
+            if !remaining.is_zero() {
+                let mut work = std::pin::pin!(work);
+                return match tokio::time::timeout(remaining, &mut work).await {
+                    Ok(output) => output,
+                    Err(_) => {
+                        self.flush().await;
+                        work.await
+                    }
+                };
+            }
+        }
+
+        self.flush().await;
+        work.await
+    }
+
+    /// The number of chunks that could not be sent because the client disconnected
+    pub(crate) fn dropped_chunks(&self) -> usize {
+        self.dropped_chunks.load(Ordering::Relaxed)
     }
 
     /// Render a new chunk of html that may change
@@ -105,12 +225,36 @@ impl<E> StreamingRenderer<E> {
         Ok(Mount { id })
     }
 
-    /// Replace a placeholder that was rendered previously
+    /// Render the scripts for changes that were not sent with a resolved placeholder
+    pub(crate) async fn render_scripts(&self, scripts: BoundaryScripts) {
+        let mut chunk = String::new();
+        if let Some(head_patch) = &scripts.head_patch {
+            _ = self.write_script(head_patch, &mut chunk);
+        }
+        if let Some(eval) = &scripts.eval {
+            _ = self.write_script(eval, &mut chunk);
+        }
+        if !chunk.is_empty() {
+            self.render(chunk).await;
+        }
+    }
+
+    fn write_script<W: Write + ?Sized>(&self, js: &str, into: &mut W) -> std::fmt::Result {
+        write!(
+            into,
+            "<script{}>{js}</script>",
+            NonceAttribute(self.nonce.as_deref())
+        )
+    }
+
+    /// Replace a placeholder that was rendered previously. Any head changes made while the placeholder resolved are
+    /// patched into the head before the placeholder is hydrated, and any evals run once it is in the dom
     pub(crate) fn replace_placeholder<W: Write + ?Sized>(
         &self,
         id: Mount,
         html: impl FnOnce(&mut W) -> std::fmt::Result,
         resolved_data: SerializedHydrationData,
+        scripts: BoundaryScripts,
         into: &mut W,
     ) -> std::fmt::Result {
         // Then replace the suspense placeholder with the new content
@@ -120,6 +264,10 @@ impl<E> StreamingRenderer<E> {
         html(into)?;
         // Restore the old path
         *self.current_path.write().unwrap() = old_path;
+        write!(into, "</div>")?;
+        if let Some(head_patch) = &scripts.head_patch {
+            self.write_script(head_patch, into)?;
+        }
         // dx_hydrate accepts 2-4 arguments. The first two are required, the rest are optional
         // The arguments are:
         // 1. The id of the nodes we are hydrating under
@@ -128,7 +276,8 @@ impl<E> StreamingRenderer<E> {
         // 4. (in debug mode) The locations of the serialized data
         write!(
             into,
-            r#"</div><script>window.dx_hydrate([{id}], "{}""#,
+            r#"<script{}>window.dx_hydrate([{id}], "{}""#,
+            NonceAttribute(self.nonce.as_deref()),
             resolved_data.data
         )?;
         #[cfg(debug_assertions)]
@@ -139,16 +288,50 @@ impl<E> StreamingRenderer<E> {
             write!(into, r#", {debug_types}, {debug_locations}"#,)?;
         }
         write!(into, r#")</script>"#)?;
+        if let Some(eval) = &scripts.eval {
+            self.write_script(eval, into)?;
+        }
 
         Ok(())
     }
 
-    /// Close the stream with an error
-    pub(crate) fn close_with_error(&self, error: E) {
-        _ = self.channel.write().unwrap().start_send(Err(error));
+    /// Close the stream after an error that happened once the initial chunk was sent. Instead of ending the body
+    /// with an error, the error is sent down in band so the client receives a well formed document:
+    /// - If the error happened while resolving a placeholder, the placeholder is replaced with an empty hidden
+    ///   node and the error is sent as its resolved data. The client raises the error in the nearest error boundary
+    /// - The document is then closed with `close_document`
+    pub(crate) async fn close_with_error(
+        &self,
+        failed_placeholder: Option<(Mount, SerializedHydrationData)>,
+        close_document: impl Display,
+    ) {
+        let mut chunk = String::new();
+        if let Some((id, error)) = failed_placeholder {
+            // Writing into a string with an empty placeholder cannot fail
+            _ = self.replace_placeholder(
+                id,
+                |_| Ok(()),
+                error,
+                BoundaryScripts::default(),
+                &mut chunk,
+            );
+        }
+        _ = write!(chunk, "{close_document}");
+        self.render(chunk).await;
+        self.flush().await;
+        self.channel.write().unwrap().close_channel();
     }
 }
 
+/// Scripts that are sent down with a resolved placeholder
+#[derive(Default)]
+pub(crate) struct BoundaryScripts {
+    /// Applies the head changes made while the placeholder resolved. This runs before the placeholder is hydrated
+    pub(crate) head_patch: Option<String>,
+    /// Runs the javascript evaluated while the placeholder resolved. This runs after the placeholder is in the dom
+    pub(crate) eval: Option<String>,
+}
+
 /// A mounted placeholder in the dom that may change in the future
 #[derive(Clone, Debug)]
 pub(crate) struct Mount {
@@ -160,3 +343,123 @@ impl Display for Mount {
         write!(f, "{}", self.id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{ServerDocument, HEAD_PATCH_JS};
+    use dioxus_document::{Document, MetaProps};
+    use futures_channel::mpsc::{channel, Receiver};
+
+    fn renderer(
+        flush_policy: FlushPolicy,
+    ) -> (
+        StreamingRenderer,
+        Receiver<Result<Bytes, std::convert::Infallible>>,
+    ) {
+        let (tx, rx) = channel(16);
+        let renderer = StreamingRenderer::new("<head></head>", tx, None, flush_policy);
+        (renderer, rx)
+    }
+
+    /// The next frame that was sent, if any
+    fn next_frame(rx: &mut Receiver<Result<Bytes, std::convert::Infallible>>) -> Option<Bytes> {
+        rx.try_recv().ok().map(|frame| frame.unwrap())
+    }
+
+    #[tokio::test]
+    async fn small_chunks_are_sent_as_one_frame() {
+        let (renderer, mut rx) = renderer(FlushPolicy {
+            max_bytes: 1024,
+            max_delay: Duration::from_secs(60 * 60),
+        });
+
+        // The head is sent without waiting for the flush policy
+        assert_eq!(next_frame(&mut rx).unwrap(), "<head></head>");
+
+        renderer.render("<div>").await;
+        renderer.render("hello").await;
+        renderer.render("</div>").await;
+        assert!(next_frame(&mut rx).is_none());
+
+        renderer.flush().await;
+        assert_eq!(next_frame(&mut rx).unwrap(), "<div>hello</div>");
+        assert!(next_frame(&mut rx).is_none());
+    }
+
+    #[tokio::test]
+    async fn chunks_are_sent_once_over_the_byte_threshold() {
+        let (renderer, mut rx) = renderer(FlushPolicy {
+            max_bytes: 8,
+            max_delay: Duration::from_secs(60 * 60),
+        });
+        next_frame(&mut rx);
+
+        renderer.render("<p>").await;
+        assert!(next_frame(&mut rx).is_none());
+        renderer.render("</p><p>").await;
+        assert_eq!(next_frame(&mut rx).unwrap(), "<p></p><p>");
+
+        // Frames after the first one start from an empty buffer
+        renderer.render("</p>").await;
+        renderer.flush().await;
+        assert_eq!(next_frame(&mut rx).unwrap(), "</p>");
+    }
+
+    #[test]
+    fn late_head_elements_are_patched_before_the_boundary_hydrates() {
+        let (tx, _rx) = channel(16);
+        let renderer: StreamingRenderer =
+            StreamingRenderer::new("", tx, Some("abc".to_string()), FlushPolicy::default());
+        let mut html = String::new();
+        let mount = renderer
+            .render_placeholder(|to| write!(to, "loading"), &mut html)
+            .unwrap();
+
+        // The boundary resolves after the head was sent and sets the title and a meta tag
+        let document = ServerDocument::with_nonce(Some("abc".to_string()));
+        document.start_streaming();
+        document.set_title("</title><script>alert(1)</script>".to_string());
+        document.create_meta(
+            MetaProps::builder()
+                .name("description".to_string())
+                .content("</script><script>alert(2)</script>".to_string())
+                .build(),
+        );
+        let scripts = BoundaryScripts {
+            head_patch: document.take_head_patch(),
+            eval: None,
+        };
+
+        let mut html = String::new();
+        renderer
+            .replace_placeholder(
+                mount,
+                |to| write!(to, "loaded"),
+                SerializedHydrationData {
+                    data: String::new(),
+                    #[cfg(debug_assertions)]
+                    debug_types: "[]".to_string(),
+                    #[cfg(debug_assertions)]
+                    debug_locations: "[]".to_string(),
+                },
+                scripts,
+                &mut html,
+            )
+            .unwrap();
+
+        let (patch, hydrate) = html
+            .strip_prefix(r#"<div id="ds-0-r" hidden>loaded</div>"#)
+            .and_then(|html| html.split_once("</script>"))
+            .unwrap_or_else(|| panic!("{html}"));
+        let patch = patch
+            .strip_prefix(&format!(r#"<script nonce="abc">{HEAD_PATCH_JS}("#))
+            .unwrap_or_else(|| panic!("{patch}"));
+        // Nothing in the patch can end the script early
+        assert!(!patch.contains('<'), "{patch}");
+        assert!(patch.contains(r#""\u003c/title>\u003cscript>alert(1)\u003c/script>""#));
+        assert!(patch.contains("alert(2)"), "{patch}");
+        assert!(hydrate.starts_with(r#"<script nonce="abc">window.dx_hydrate([0], """#));
+        assert_eq!(html.matches("</script>").count(), 2, "{html}");
+    }
+}
@@ -0,0 +1,208 @@
+//! The Cloudflare Workers environment and execution context for a request.
+use axum::extract::FromRequestParts;
+use dioxus_fullstack_core::FullstackContext;
+use http::{request::Parts, StatusCode};
+use std::sync::Arc;
+
+/// The Worker `env` and execution context for a request. The Worker entry point attaches them to the request with
+/// [`WorkerBindings::attach`], and they are moved into the [`FullstackContext`] of the server function or render that
+/// handles the request.
+///
+/// The types are generic so this crate doesn't depend on a specific Workers binding. With the `worker` crate, this is
+/// `WorkerBindings<worker::Env, worker::Context>`. Tests can use a mock env type instead:
+///
+/// ```rust, no_run
+/// use dioxus_server::WorkerBindings;
+///
+/// #[derive(Default)]
+/// struct MockEnv {
+///     api_key: String,
+/// }
+///
+/// async fn call_server_function() -> axum::response::Response {
+///     use tower::ServiceExt;
+///
+///     let mut request = http::Request::post("/api/get_user").body(axum::body::Body::empty()).unwrap();
+///     WorkerBindings::new(MockEnv::default(), ()).attach(&mut request);
+///     dioxus_server::server_functions_router(None).oneshot(request).await.unwrap()
+/// }
+///
+/// // Inside the server function
+/// async fn get_user() -> Result<String, &'static str> {
+///     let bindings = dioxus_server::worker_bindings::<MockEnv, ()>().ok_or("missing env")?;
+///     Ok(bindings.env().api_key.clone())
+/// }
+/// ```
+pub struct WorkerBindings<E, C = ()> {
+    env: Arc<E>,
+    ctx: Arc<C>,
+}
+
+// SAFETY: Without the atomics target feature, wasm32-unknown-unknown is single-threaded. The bindings hold JavaScript
+// values that are not `Send` or `Sync`, but there are no other threads they could be moved to or shared with. Builds
+// with atomics can have threads, so the bindings are only `Send` and `Sync` there if the env and context are.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl<E, C> Send for WorkerBindings<E, C> {}
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl<E, C> Sync for WorkerBindings<E, C> {}
+
+impl<E, C> Clone for WorkerBindings<E, C> {
+    fn clone(&self) -> Self {
+        Self {
+            env: self.env.clone(),
+            ctx: self.ctx.clone(),
+        }
+    }
+}
+
+impl<E: 'static, C: 'static> WorkerBindings<E, C> {
+    /// Create bindings from the Worker `env` and execution context
+    pub fn new(env: E, ctx: C) -> Self {
+        Self {
+            env: Arc::new(env),
+            ctx: Arc::new(ctx),
+        }
+    }
+
+    /// The Worker `env` with the KV namespaces, D1 databases, secrets and other bindings
+    pub fn env(&self) -> &E {
+        &self.env
+    }
+
+    /// The execution context of the request
+    pub fn ctx(&self) -> &C {
+        &self.ctx
+    }
+
+    /// Attach the bindings to a request before it is passed to the router
+    pub fn attach<B>(self, request: &mut http::Request<B>)
+    where
+        Self: Send + Sync,
+    {
+        request.extensions_mut().insert(self);
+    }
+}
+
+/// Get the Worker bindings attached to the current request with [`WorkerBindings::attach`]. This works inside server
+/// functions and while rendering the app.
+///
+/// Returns `None` outside of a request or if no bindings of these types were attached.
+pub fn worker_bindings<E: 'static, C: 'static>() -> Option<WorkerBindings<E, C>>
+where
+    WorkerBindings<E, C>: Send + Sync,
+{
+    FullstackContext::current()?.extension()
+}
+
+impl<S, E: 'static, C: 'static> FromRequestParts<S> for WorkerBindings<E, C>
+where
+    S: Send + Sync,
+    Self: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Self>().cloned().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "The Worker bindings were not attached to the request",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{server_functions_router, ServerFunction};
+    use axum::{
+        body::Body,
+        extract::{Request, State},
+        response::{IntoResponse, Response},
+    };
+    use http::Method;
+    use std::{future::Future, pin::Pin};
+    use tower::ServiceExt;
+
+    struct MockEnv {
+        api_key: &'static str,
+    }
+
+    struct MockCtx {
+        request_id: u32,
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn read_bindings(
+        _: State<FullstackContext>,
+        request: Request,
+    ) -> Pin<Box<dyn Future<Output = Response>>> {
+        Box::pin(async move {
+            let Some(bindings) = worker_bindings::<MockEnv, MockCtx>() else {
+                return (StatusCode::INTERNAL_SERVER_ERROR, "missing bindings").into_response();
+            };
+            // The extractor reads the same bindings from the request
+            let (mut parts, _) = request.into_parts();
+            let extracted =
+                match WorkerBindings::<MockEnv, MockCtx>::from_request_parts(&mut parts, &()).await
+                {
+                    Ok(extracted) => extracted,
+                    Err(rejection) => return rejection.into_response(),
+                };
+            assert!(Arc::ptr_eq(&bindings.env, &extracted.env));
+            format!("{} {}", bindings.env().api_key, bindings.ctx().request_id).into_response()
+        })
+    }
+
+    inventory::submit! {
+        ServerFunction::new(Method::POST, "/api/read_worker_bindings", || {
+            ServerFunction::make_handler(Method::POST, read_bindings)
+        })
+    }
+
+    async fn call(request: http::Request<Body>) -> (StatusCode, String) {
+        let response = server_functions_router(None)
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn server_functions_read_attached_bindings() {
+        let mut request = http::Request::post("/api/read_worker_bindings")
+            .body(Body::empty())
+            .unwrap();
+        WorkerBindings::new(MockEnv { api_key: "secret" }, MockCtx { request_id: 7 })
+            .attach(&mut request);
+
+        assert_eq!(
+            call(request).await,
+            (StatusCode::OK, "secret 7".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn bindings_are_missing_without_attach() {
+        let request = http::Request::post("/api/read_worker_bindings")
+            .body(Body::empty())
+            .unwrap();
+
+        let (status, body) = call(request).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body, "missing bindings");
+    }
+
+    #[tokio::test]
+    async fn bindings_of_other_types_are_not_returned() {
+        let mut request = http::Request::post("/api/read_worker_bindings")
+            .body(Body::empty())
+            .unwrap();
+        WorkerBindings::new(MockEnv { api_key: "secret" }, ()).attach(&mut request);
+
+        let (status, _) = call(request).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}